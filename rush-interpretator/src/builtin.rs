@@ -0,0 +1,215 @@
+//! Functions available to every script run by the `rush` binary.
+
+use crate::{int_to_float, Engine, List, Map, Result, RuntimeError, RuntimeResult, Value};
+
+impl Engine {
+    /// Register the builtin functions: printing, `type_of`, `cd` and `pwd`,
+    /// conversions between numbers and strings, and helpers for lists and
+    /// maps.
+    pub fn with_builtins(self) -> Self {
+        self.with_fn_raw("print", print)
+            .with_fn_raw("println", |args| {
+                let ret = print(args)?;
+                println!();
+                Ok(ret)
+            })
+            .with_fn_raw("type_of", type_of)
+            .with_fn_raw("len", len)
+            .with_fn("int", to_int)
+            .with_fn("float", to_float)
+            .with_fn("str", |val: Value| Ok(val.to_string().into()))
+            .with_fn("parse_int", parse_int)
+            .with_fn("push", |xs: List, val: Value| {
+                xs.push(val);
+                Ok(Value::Unit)
+            })
+            .with_fn("keys", |m: Map| {
+                Ok(m.to_map().into_keys().collect::<Vec<_>>().into())
+            })
+            .with_ctx_fn("cd", |ctx, args| {
+                let [dir] =
+                    <[Value; 1]>::try_from(args).map_err(|args| RuntimeError::ArgumentError {
+                        ident: "cd".to_owned(),
+                        expected: 1,
+                        found: args.len(),
+                    })?;
+                ctx.set_cwd(dir.to_string())?;
+                Ok(Value::Unit)
+            })
+            .with_ctx_fn("pwd", |ctx, args| {
+                if !args.is_empty() {
+                    return Err(RuntimeError::ArgumentError {
+                        ident: "pwd".to_owned(),
+                        expected: 0,
+                        found: args.len(),
+                    }
+                    .into());
+                }
+                Ok(ctx.cwd().display().to_string().into())
+            })
+            .with_ctx_fn("map", |ctx, args| {
+                let (xs, func) = list_and_fn("map", args)?;
+                let mapped = xs
+                    .to_vec()
+                    .into_iter()
+                    .map(|x| ctx.call_value(&func, vec![x]))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(mapped.into())
+            })
+            .with_ctx_fn("filter", |ctx, args| {
+                let (xs, func) = list_and_fn("filter", args)?;
+                let mut kept = vec![];
+                for x in xs.to_vec() {
+                    if ctx
+                        .call_value(&func, vec![x.clone()])?
+                        .rt_cast::<bool>("<filter>")?
+                    {
+                        kept.push(x);
+                    }
+                }
+                Ok(kept.into())
+            })
+            .with_ctx_fn("each", |ctx, args| {
+                let (xs, func) = list_and_fn("each", args)?;
+                for x in xs.to_vec() {
+                    drop(ctx.call_value(&func, vec![x])?);
+                }
+                Ok(Value::Unit)
+            })
+    }
+}
+
+#[allow(clippy::unnecessary_wraps)]
+fn print(args: Vec<Value>) -> RuntimeResult<Value> {
+    let mut iter = args.into_iter().peekable();
+    while let Some(arg) = iter.next() {
+        if iter.peek().is_none() {
+            print!("{arg}");
+            break;
+        }
+        print!("{arg} ");
+    }
+    Ok(Value::Unit)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn type_of(args: Vec<Value>) -> RuntimeResult<Value> {
+    if args.len() != 1 {
+        return Err(RuntimeError::ArgumentError {
+            ident: "typeof".to_string(),
+            expected: 1,
+            found: args.len(),
+        });
+    }
+    Ok(Value::from(args[0].type_name()))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn len(args: Vec<Value>) -> RuntimeResult<Value> {
+    let len = match args.as_slice() {
+        [Value::List(xs)] => xs.len(),
+        [Value::Map(m)] => m.len(),
+        [Value::Str(s)] => s.chars().count(),
+        [other] => {
+            return Err(RuntimeError::TypeError {
+                ident: "len".to_owned(),
+                expected: "list, map or str".to_owned(),
+                found: other.type_name().to_owned(),
+            });
+        }
+        _ => {
+            return Err(RuntimeError::ArgumentError {
+                ident: "len".to_owned(),
+                expected: 1,
+                found: args.len(),
+            });
+        }
+    };
+    Ok(Value::Int(len.try_into().unwrap_or(i64::MAX)))
+}
+
+/// 2^63, the first float past the ints
+const INT_LIMIT: f64 = 9_223_372_036_854_775_808.0;
+
+/// `int(val)`: floats are truncated towards zero, strings are parsed as
+/// decimal ints.
+#[allow(clippy::needless_pass_by_value, clippy::cast_possible_truncation)]
+fn to_int(val: Value) -> RuntimeResult<Value> {
+    let conversion = |reason: &str| RuntimeError::Conversion {
+        value: val.to_string(),
+        to: "int",
+        reason: reason.to_owned(),
+    };
+    let int = match &val {
+        Value::Int(int) => *int,
+        Value::Float(float) if float.is_nan() => return Err(conversion("not a number")),
+        Value::Float(float) if (-INT_LIMIT..INT_LIMIT).contains(&float.trunc()) => {
+            float.trunc() as i64
+        }
+        Value::Float(_) => return Err(conversion("out of range")),
+        Value::Bool(bool) => i64::from(*bool),
+        Value::Str(str) => str
+            .trim()
+            .parse()
+            .map_err(|err: std::num::ParseIntError| conversion(&err.to_string()))?,
+        other => {
+            return Err(RuntimeError::TypeError {
+                ident: "int".to_owned(),
+                expected: "int, float, bool or str".to_owned(),
+                found: other.type_name().to_owned(),
+            });
+        }
+    };
+    Ok(Value::Int(int))
+}
+
+/// `float(val)`: strings are parsed, e.g. `"1.5"`, `"1e3"` or `"inf"`.
+#[allow(clippy::needless_pass_by_value)]
+fn to_float(val: Value) -> RuntimeResult<Value> {
+    let float = match &val {
+        Value::Int(int) => int_to_float(*int),
+        Value::Float(float) => *float,
+        Value::Str(str) => str
+            .trim()
+            .parse()
+            .map_err(|err: std::num::ParseFloatError| RuntimeError::Conversion {
+                value: val.to_string(),
+                to: "float",
+                reason: err.to_string(),
+            })?,
+        other => {
+            return Err(RuntimeError::TypeError {
+                ident: "float".to_owned(),
+                expected: "int, float or str".to_owned(),
+                found: other.type_name().to_owned(),
+            });
+        }
+    };
+    Ok(Value::Float(float))
+}
+
+/// `parse_int(str, radix)`, e.g. `parse_int("ff", 16)`.
+fn parse_int(str: String, radix: i64) -> RuntimeResult<Value> {
+    let radix = u32::try_from(radix)
+        .ok()
+        .filter(|radix| (2..=36).contains(radix))
+        .ok_or(RuntimeError::InvalidRadix(radix))?;
+    i64::from_str_radix(str.trim(), radix)
+        .map(Value::Int)
+        .map_err(|err| RuntimeError::Conversion {
+            value: str,
+            to: "int",
+            reason: err.to_string(),
+        })
+}
+
+fn list_and_fn(name: &str, args: Vec<Value>) -> RuntimeResult<(List, Value)> {
+    let [xs, func]: [Value; 2] =
+        args.try_into()
+            .map_err(|args: Vec<Value>| RuntimeError::ArgumentError {
+                ident: name.to_owned(),
+                expected: 2,
+                found: args.len(),
+            })?;
+    Ok((xs.rt_cast(name)?, func))
+}
@@ -0,0 +1,147 @@
+use parser::ast::BinOpKind;
+
+use crate::{RuntimeError, RuntimeResult, Value};
+
+/// What integer arithmetic does when the result doesn't fit in an `int`.
+/// Division by zero is an error whatever the mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Fail with [`RuntimeError::Overflow`]
+    #[default]
+    Checked,
+    /// Wrap around at the bounds of `int`, like two's complement hardware
+    Wrapping,
+    /// Clamp to the bounds of `int`
+    Saturating,
+}
+
+impl Overflow {
+    /// Apply an arithmetic operator to two ints. `/` and `%` round towards
+    /// negative infinity, so that `a == a / b * b + a % b` and `a % b` has
+    /// the sign of `b`: `-7 / 2 == -4` and `-7 % 2 == 1`.
+    pub fn int_op(self, op: &BinOpKind, left: i64, right: i64) -> RuntimeResult<i64> {
+        if matches!(op, BinOpKind::Div | BinOpKind::Rem) && right == 0 {
+            return Err(RuntimeError::DivisionByZero);
+        }
+        let overflow = || RuntimeError::Overflow {
+            op: op.as_str(),
+            left,
+            right,
+        };
+        let res = match (op, self) {
+            (BinOpKind::Add, Self::Checked) => left.checked_add(right).ok_or_else(overflow)?,
+            (BinOpKind::Add, Self::Wrapping) => left.wrapping_add(right),
+            (BinOpKind::Add, Self::Saturating) => left.saturating_add(right),
+            (BinOpKind::Sub, Self::Checked) => left.checked_sub(right).ok_or_else(overflow)?,
+            (BinOpKind::Sub, Self::Wrapping) => left.wrapping_sub(right),
+            (BinOpKind::Sub, Self::Saturating) => left.saturating_sub(right),
+            (BinOpKind::Mul, Self::Checked) => left.checked_mul(right).ok_or_else(overflow)?,
+            (BinOpKind::Mul, Self::Wrapping) => left.wrapping_mul(right),
+            (BinOpKind::Mul, Self::Saturating) => left.saturating_mul(right),
+            // Only `int::MIN / -1` overflows, and it divides exactly
+            (BinOpKind::Div, Self::Checked) => {
+                floor_div(left, right, left.checked_div(right).ok_or_else(overflow)?)
+            }
+            (BinOpKind::Div, Self::Wrapping) => floor_div(left, right, left.wrapping_div(right)),
+            (BinOpKind::Div, Self::Saturating) => {
+                floor_div(left, right, left.saturating_div(right))
+            }
+            // The remainder always fits, `int::MIN % -1` is 0
+            (BinOpKind::Rem, _) => floor_rem(left.wrapping_rem(right), right),
+            (BinOpKind::Pow, mode) => {
+                let exp =
+                    u64::try_from(right).map_err(|_| RuntimeError::NegativeExponent(right))?;
+                // Only 0, 1 and -1 don't overflow with an exponent beyond
+                // `u32::MAX`, keep its parity for -1
+                let small_exp =
+                    u32::try_from(exp).unwrap_or_else(|_| u32::MAX - u32::from(exp % 2 == 0));
+                match mode {
+                    Self::Checked => left.checked_pow(small_exp).ok_or_else(overflow)?,
+                    Self::Wrapping => wrapping_pow(left, exp),
+                    Self::Saturating => left.saturating_pow(small_exp),
+                }
+            }
+            _ => unreachable!("`{}` is not an arithmetic operator", op.as_str()),
+        };
+        Ok(res)
+    }
+
+    /// Negate an int.
+    pub fn int_neg(self, val: i64) -> RuntimeResult<i64> {
+        match self {
+            Self::Checked => val.checked_neg().ok_or(RuntimeError::Overflow {
+                op: "-",
+                left: 0,
+                right: val,
+            }),
+            Self::Wrapping => Ok(val.wrapping_neg()),
+            Self::Saturating => Ok(val.saturating_neg()),
+        }
+    }
+}
+
+/// Value of a number as a float, ints being promoted.
+pub(super) const fn as_float(val: &Value) -> Option<f64> {
+    match val {
+        Value::Int(val) => Some(int_to_float(*val)),
+        Value::Float(val) => Some(*val),
+        _ => None,
+    }
+}
+
+/// The float nearest to `val`, ints beyond 2^53 may not have an exact one.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub const fn int_to_float(val: i64) -> f64 {
+    val as f64
+}
+
+/// Apply an arithmetic operator to two floats, with IEEE 754 semantics:
+/// dividing by zero gives an infinity or NaN.
+pub(super) fn float_op(op: &BinOpKind, left: f64, right: f64) -> f64 {
+    match op {
+        BinOpKind::Add => left + right,
+        BinOpKind::Sub => left - right,
+        BinOpKind::Mul => left * right,
+        BinOpKind::Div => left / right,
+        BinOpKind::Rem => floor_rem(left % right, right),
+        BinOpKind::Pow => left.powf(right),
+        _ => unreachable!("`{}` is not an arithmetic operator", op.as_str()),
+    }
+}
+
+/// `base ** exp` wrapping around at the bounds of `int`, by squaring for each
+/// bit of `exp`.
+const fn wrapping_pow(mut base: i64, mut exp: u64) -> i64 {
+    let mut res: i64 = 1;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            res = res.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exp >>= 1;
+    }
+    res
+}
+
+/// Round the truncated quotient `quot` of `left / right` down.
+const fn floor_div(left: i64, right: i64, quot: i64) -> i64 {
+    if left.wrapping_rem(right) != 0 && (left < 0) != (right < 0) {
+        quot - 1
+    } else {
+        quot
+    }
+}
+
+/// Give the truncated remainder `rem` the sign of the divisor `right`.
+fn floor_rem<T>(rem: T, right: T) -> T
+where
+    T: Copy + Default + PartialOrd + std::ops::Add<Output = T>,
+{
+    let zero = T::default();
+    if rem != zero && (rem < zero) != (right < zero) {
+        rem + right
+    } else {
+        rem
+    }
+}
@@ -0,0 +1,46 @@
+use std::{fmt, rc::Rc};
+
+use crate::{FileLoader, ModuleLoader, Overflow, TraceSink};
+
+/// Options of an [`Engine`](crate::Engine) that change how scripts are run.
+#[must_use]
+#[derive(Clone)]
+pub struct Config {
+    /// Turn a ``$`cmd` `` that exits with a non-zero status into an error,
+    /// like `set -e`. ``$?`cmd` `` is meant for inspecting failures and never
+    /// fails because of its exit status.
+    pub fail_on_nonzero: bool,
+    /// Run each command with `sh -c` instead of spawning it directly, for
+    /// shell features like globs, `$VAR`, `$(..)` or `&&`.
+    pub shell: bool,
+    /// Report every command before running it, like `set -x`. Off when `None`.
+    pub trace: Option<Rc<dyn TraceSink>>,
+    /// What integer arithmetic does on overflow, fail by default.
+    pub overflow: Overflow,
+    /// Where `use` finds modules, files next to the importing script by
+    /// default.
+    pub module_loader: Rc<dyn ModuleLoader>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            fail_on_nonzero: false,
+            shell: false,
+            trace: None,
+            overflow: Overflow::default(),
+            module_loader: Rc::new(FileLoader::new()),
+        }
+    }
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("fail_on_nonzero", &self.fail_on_nonzero)
+            .field("shell", &self.shell)
+            .field("trace", &self.trace.is_some())
+            .field("overflow", &self.overflow)
+            .finish_non_exhaustive()
+    }
+}
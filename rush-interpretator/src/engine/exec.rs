@@ -0,0 +1,69 @@
+use std::{borrow::Cow, collections::BTreeMap, process::Output};
+
+use crate::{CommandError, CommandResult, Value};
+
+/// Structured result of ``$?`cmd` ``: a map with the exit `status` (`()` if the
+/// command was killed by a signal), `stdout`, `stderr` and whether it
+/// exited with `success`.
+pub fn output_value(output: &Output) -> Value {
+    let status = output
+        .status
+        .code()
+        .map_or(Value::Unit, |code| i64::from(code).into());
+    let entries = BTreeMap::from([
+        ("status".to_owned(), status),
+        (
+            "stdout".to_owned(),
+            String::from_utf8_lossy(&output.stdout).into_owned().into(),
+        ),
+        (
+            "stderr".to_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned().into(),
+        ),
+        ("success".to_owned(), output.status.success().into()),
+    ]);
+    entries.into()
+}
+
+/// Fail if the command did not exit successfully.
+pub fn check_status(cmd: &str, output: &Output) -> CommandResult<()> {
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(CommandError::NonZeroExit {
+        cmd: cmd.to_owned(),
+        code: output.status.code(),
+    })
+}
+
+/// Quote `word` so that a POSIX shell reads it back as a single word with no
+/// expansion. Words made only of characters without special meaning are left
+/// as they are.
+///
+/// The result is only safe to splice into shell text outside of quotes:
+/// inside `'..'` or `"..."`, its own quotes would close and reopen the
+/// surrounding ones instead, so those must be closed before it.
+#[must_use]
+pub fn shell_quote(word: &str) -> Cow<'_, str> {
+    // Not `=`, which makes `NAME=value` an assignment when it's the first word
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "_-+./:,@%".contains(c);
+    if !word.is_empty() && word.chars().all(is_plain) {
+        return Cow::Borrowed(word);
+    }
+    Cow::Owned(format!("'{}'", word.replace('\'', r"'\''")))
+}
+
+/// Shell quoted form of a value: a list becomes one word per item. Like
+/// [`shell_quote`], only safe outside of quotes.
+#[must_use]
+pub fn shell_words(val: &Value) -> String {
+    match val {
+        Value::List(items) => items
+            .to_vec()
+            .iter()
+            .map(shell_words)
+            .collect::<Vec<_>>()
+            .join(" "),
+        other => shell_quote(&other.to_string()).into_owned(),
+    }
+}
@@ -0,0 +1,73 @@
+use parser::Span;
+
+use crate::{CommandError, Error, Frame, RuntimeError, Value};
+
+/// Reason for leaving the normal evaluation order.
+///
+/// Control flow items like `return` and `break` travel outward through the same
+/// channel as errors so that every frame between the signal and its handler
+/// unwinds (and pops its scope) the same way, but they are not errors
+/// themselves: they get turned back into values where they are handled.
+///
+/// Control flow carries the span of the item it comes from, to locate the
+/// error it becomes if it escapes every handler.
+#[derive(Debug)]
+pub enum Interrupt {
+    Error(Error),
+    Return(Value, Span),
+    Break(Value, Span),
+    Continue(Span),
+}
+
+impl Interrupt {
+    /// Locate an error at `span`, see [`Error::with_span`]. Control flow is
+    /// left as is.
+    #[must_use]
+    pub fn with_span(self, span: Span) -> Self {
+        match self {
+            Self::Error(err) => Self::Error(err.with_span(span)),
+            other => other,
+        }
+    }
+
+    /// Add a frame to the stack trace of an error, see [`Error::with_frame`].
+    #[must_use]
+    pub fn with_frame(self, frame: Frame) -> Self {
+        match self {
+            Self::Error(err) => Self::Error(err.with_frame(frame)),
+            other => other,
+        }
+    }
+
+    /// Convert an interrupt that escaped every handler into an error.
+    #[must_use]
+    pub fn into_error(self) -> Error {
+        let (error, span) = match self {
+            Self::Error(err) => return err,
+            Self::Return(_, span) => (RuntimeError::ReturnOutsideFn, span),
+            Self::Break(_, span) => (RuntimeError::BreakOutsideLoop, span),
+            Self::Continue(span) => (RuntimeError::ContinueOutsideLoop, span),
+        };
+        Error::from(error).with_span(span)
+    }
+}
+
+impl From<Error> for Interrupt {
+    fn from(err: Error) -> Self {
+        Self::Error(err)
+    }
+}
+
+impl From<RuntimeError> for Interrupt {
+    fn from(err: RuntimeError) -> Self {
+        Self::Error(err.into())
+    }
+}
+
+impl From<CommandError> for Interrupt {
+    fn from(err: CommandError) -> Self {
+        Self::Error(err.into())
+    }
+}
+
+pub type EvalResult<T = Value> = std::result::Result<T, Interrupt>;
@@ -0,0 +1,47 @@
+use std::fmt::Display;
+
+use parser::ast::ClosureExpr;
+
+use crate::{ModuleId, Value};
+
+/// Function value created by a closure expression.
+///
+/// Local variables visible where the closure is created are captured by
+/// value. Lists and maps are shared, so changes to their content are seen on
+/// both sides, while assigning to a captured variable only changes the
+/// closure's copy. Globals are not captured and are looked up on each call,
+/// in the script or module the closure is created in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClosureFn {
+    pub expr: ClosureExpr,
+    pub captured: Vec<(String, Value)>,
+    pub home: Option<ModuleId>,
+}
+
+impl ClosureFn {
+    #[must_use]
+    pub const fn new(
+        expr: ClosureExpr,
+        captured: Vec<(String, Value)>,
+        home: Option<ModuleId>,
+    ) -> Self {
+        Self {
+            expr,
+            captured,
+            home,
+        }
+    }
+}
+
+impl Display for ClosureFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ClosureFn(|")?;
+        for (i, ident) in self.expr.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", ident.name)?;
+        }
+        write!(f, "|)")
+    }
+}
@@ -3,12 +3,30 @@ use std::{
     marker::PhantomData,
 };
 
-use crate::{FnCallArg, Locked, RuntimeResult, Value};
+use crate::{Context, FnCallArg, Locked, Result, RuntimeResult, Value};
 
 pub trait ExternalFn: 'static {
     fn call(&mut self, name: &str, args: FnCallArg) -> RuntimeResult<Value>;
 }
 
+/// Native function that is handed the calling [`Context`], so it can call
+/// back into function values it receives (see [`Context::call_value`]).
+///
+/// Unlike [`ExternalFn`] this takes `&self`, which allows it to be re-entered
+/// by the functions it calls, e.g. `map` inside a closure given to `map`.
+pub trait ContextFn: 'static {
+    fn call(&self, ctx: &mut Context, name: &str, args: FnCallArg) -> Result<Value>;
+}
+
+impl<T> ContextFn for T
+where
+    T: Fn(&mut Context, FnCallArg) -> Result<Value> + 'static,
+{
+    fn call(&self, ctx: &mut Context, _: &str, args: FnCallArg) -> Result<Value> {
+        self(ctx, args)
+    }
+}
+
 impl<T: FnMut(FnCallArg) -> RuntimeResult<Value> + 'static> ExternalFn for T {
     fn call(&mut self, _: &str, args: FnCallArg) -> RuntimeResult<Value> {
         self(args)
@@ -91,22 +109,34 @@ mod impl_fns_without_fmt {
     impl_fn!(24, A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X);
 }
 
+enum NativePtr {
+    Plain(Locked<Box<dyn ExternalFn>>),
+    Context(Box<dyn ContextFn>),
+}
+
 pub struct NativeFn {
-    ptr: Locked<Box<dyn ExternalFn>>,
+    ptr: NativePtr,
     name: String,
 }
 
 impl NativeFn {
     pub fn new(ptr: impl ExternalFn, name: impl Into<String>) -> Self {
         Self {
-            ptr: Locked::new(Box::new(ptr)),
+            ptr: NativePtr::Plain(Locked::new(Box::new(ptr))),
             name: name.into(),
         }
     }
 
     pub fn new_boxed(ptr: Box<dyn ExternalFn>, name: impl Into<String>) -> Self {
         Self {
-            ptr: Locked::new(ptr),
+            ptr: NativePtr::Plain(Locked::new(ptr)),
+            name: name.into(),
+        }
+    }
+
+    pub fn new_with_ctx(ptr: impl ContextFn, name: impl Into<String>) -> Self {
+        Self {
+            ptr: NativePtr::Context(Box::new(ptr)),
             name: name.into(),
         }
     }
@@ -116,21 +146,30 @@ impl NativeFn {
         &self.name
     }
 
-    pub fn call(&self, args: FnCallArg) -> RuntimeResult<Value> {
-        (&mut *self.ptr.get_mut()).call(&self.name, args)
+    pub fn call(&self, ctx: &mut Context, args: FnCallArg) -> Result<Value> {
+        match &self.ptr {
+            NativePtr::Plain(ptr) => ptr.get_mut().call(&self.name, args).map_err(Into::into),
+            NativePtr::Context(ptr) => ptr.call(ctx, &self.name, args),
+        }
     }
 }
 
 impl Display for NativeFn {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "NativeFn({})", self.name)
     }
 }
 
 impl Debug for NativeFn {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.debug_struct("NativeFn")
-            .field("ptr", &"Box(dyn ExternalFn)")
+            .field(
+                "ptr",
+                &match self.ptr {
+                    NativePtr::Plain(_) => "Box(dyn ExternalFn)",
+                    NativePtr::Context(_) => "Box(dyn ContextFn)",
+                },
+            )
             .field("name", &self.name)
             .finish()
     }
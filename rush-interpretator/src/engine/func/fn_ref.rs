@@ -1,50 +1,56 @@
 use std::{
-    fmt::Display,
-    ops::{Deref, DerefMut},
+    fmt::{self, Debug, Display},
+    ops::Deref,
 };
 
-use crate::Ref;
+use crate::{Callable, IntoShared, Shared};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Function value. Every copy of it refers to the same function, which is
+/// freed along with the last copy, so closures created in a loop don't pile
+/// up.
+#[derive(Clone)]
 #[must_use]
 pub struct FnRef {
-    fn_ref: Ref,
+    func: Shared<Callable>,
 }
 
 impl FnRef {
-    pub const fn new(fn_ref: Ref) -> Self {
-        Self { fn_ref }
+    pub fn new(func: Callable) -> Self {
+        Self {
+            func: func.shared(),
+        }
     }
+}
 
-    #[must_use]
-    pub const fn inner(&self) -> Ref {
-        self.fn_ref
+impl From<Callable> for FnRef {
+    fn from(func: Callable) -> Self {
+        Self::new(func)
     }
 }
 
-impl From<Ref> for FnRef {
-    fn from(ref_: Ref) -> Self {
-        Self { fn_ref: ref_ }
+/// Copies of the same function are equal, different functions never are.
+impl PartialEq for FnRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.func.ptr_eq(&other.func)
     }
 }
 
 impl Display for FnRef {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        // write!(f, "FnRef({}, {})", self.fn_ref, self)
-        write!(f, "FnRef({})", self.fn_ref)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FnRef({})", *self.func)
     }
 }
 
-impl Deref for FnRef {
-    type Target = Ref;
-
-    fn deref(&self) -> &Ref {
-        &self.fn_ref
+impl Debug for FnRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FnRef({})", *self.func)
     }
 }
 
-impl DerefMut for FnRef {
-    fn deref_mut(&mut self) -> &mut Ref {
-        &mut self.fn_ref
+impl Deref for FnRef {
+    type Target = Callable;
+
+    fn deref(&self) -> &Callable {
+        &self.func
     }
 }
@@ -1,21 +1,29 @@
 #![allow(clippy::module_name_repetitions)]
 
-mod_use::mod_use![fn_ref, external, script];
+mod_use::mod_use![fn_ref, external, script, closure];
 
-use parser::ast::{Expr, FnCall, FnDef};
+use std::fmt::{self, Display};
 
-use crate::{Context, Result, RuntimeError, ToResult, Value};
+use parser::{
+    ast::{ClosureExpr, Expr, FnCall, FnDef},
+    Span,
+};
+
+use crate::{
+    Context, EvalResult, Frame, FrameKind, Interrupt, ModuleId, RuntimeError, ToResult, Value,
+};
 
 pub type FnCallArg = Vec<Value>;
-pub type FnCallParam<'r, 'a> = &'r [Expr<'a>];
+pub type FnCallParam<'r> = &'r [Expr];
 
 #[must_use]
-pub enum Callable<'a> {
+pub enum Callable {
     Native(NativeFn),
-    Script(ScriptFn<'a>),
+    Script(ScriptFn),
+    Closure(ClosureFn),
 }
 
-impl<'a> Callable<'a> {
+impl Callable {
     pub fn native(ptr: impl ExternalFn, name: impl Into<String>) -> Self {
         Self::Native(NativeFn::new(ptr, name.into()))
     }
@@ -24,56 +32,136 @@ impl<'a> Callable<'a> {
         Self::Native(NativeFn::new_boxed(ptr, name.into()))
     }
 
-    pub fn call(&self, ctx: &mut Context<'a>, fn_call: &FnCall<'a>) -> Result<'a, Value> {
-        let name = fn_call.ident.name;
-
+    /// Name the function is defined with, `<closure>` for closures.
+    #[must_use]
+    pub fn name(&self) -> &str {
         match self {
-            Callable::Native(native_fn) => {
-                let args = fn_call
-                    .args
-                    .iter()
-                    .map(|arg| ctx.eval_expr(arg))
-                    .collect::<Result<Vec<_>>>()?;
-                native_fn.call(args).map(Into::into).map_err(Into::into)
-            }
-            Callable::Script(script_fn) => {
+            Self::Native(native_fn) => native_fn.name(),
+            Self::Script(script_fn) => &script_fn.def.ident.name,
+            Self::Closure(_) => "<closure>",
+        }
+    }
+
+    pub fn call(&self, ctx: &mut Context, fn_call: &FnCall) -> EvalResult {
+        let args = fn_call
+            .args
+            .iter()
+            .map(|arg| ctx.eval_expr(arg))
+            .collect::<EvalResult<Vec<_>>>()?;
+        self.call_values(ctx, &fn_call.ident.name, Some(fn_call.span), args)
+    }
+
+    /// Call with already evaluated arguments. `name` is used for the new scope,
+    /// in error messages and in the stack trace of errors, where `call_site` is
+    /// the call expression, if called from the script.
+    pub fn call_values(
+        &self,
+        ctx: &mut Context,
+        name: &str,
+        call_site: Option<Span>,
+        args: FnCallArg,
+    ) -> EvalResult {
+        let res = match self {
+            Self::Native(native_fn) => native_fn.call(ctx, args).map_err(Into::into),
+            Self::Script(script_fn) => {
                 let def = &script_fn.def;
-                let args = &fn_call.args;
-                if def.params.len() != args.len() {
-                    RuntimeError::ArgumentError {
-                        ident: def.ident.name.to_owned(),
-                        expected: def.params.len(),
-                        found: args.len(),
-                    }
-                    .err()?;
+                Self::check_args(&def.ident.name, def.params.len(), args.len())?;
+                ctx.enter_scope(name)?;
+                for (param, arg_val) in std::iter::zip(&def.params, args) {
+                    ctx.current_mut().new_var(&param.name, arg_val);
                 }
+                let home = std::mem::replace(&mut ctx.home, script_fn.home);
+                let res = ctx.eval_block_items(&def.body);
+                ctx.home = home;
+                ctx.pop_scope();
+                Self::finish(res)
+            }
+            Self::Closure(closure) => {
+                let params = &closure.expr.params;
+                Self::check_args(name, params.len(), args.len())?;
                 ctx.enter_scope(name)?;
-                for (param, arg) in std::iter::zip(&def.params, args) {
-                    let arg_val = ctx.eval_expr(arg)?;
-                    ctx.current_mut().new_var(param.name, arg_val);
+                for (captured, val) in &closure.captured {
+                    ctx.current_mut().new_var(captured, val.clone());
                 }
-                for item in &script_fn.def.body.items {
-                    drop(ctx.eval_item(item)?);
+                for (param, arg_val) in std::iter::zip(params, args) {
+                    ctx.current_mut().new_var(&param.name, arg_val);
                 }
+                let home = std::mem::replace(&mut ctx.home, closure.home);
+                let res = ctx.eval_expr(&closure.expr.body);
+                ctx.home = home;
                 ctx.pop_scope();
-                Ok(Value::Unit)
+                Self::finish(res)
+            }
+        };
+        res.map_err(|interrupt| {
+            interrupt.with_frame(Frame {
+                name: name.to_owned(),
+                call_site,
+                kind: self.frame_kind(),
+            })
+        })
+    }
+
+    const fn frame_kind(&self) -> FrameKind {
+        match self {
+            Self::Native(_) => FrameKind::Native,
+            Self::Script(_) | Self::Closure(_) => FrameKind::Script,
+        }
+    }
+
+    fn check_args(name: &str, expected: usize, found: usize) -> EvalResult<()> {
+        if expected != found {
+            RuntimeError::ArgumentError {
+                ident: name.to_owned(),
+                expected,
+                found,
             }
+            .err()?;
+        }
+        Ok(())
+    }
+
+    /// Turn the result of a function body into the result of the call.
+    fn finish(res: EvalResult) -> EvalResult {
+        match res {
+            Ok(val) | Err(Interrupt::Return(val, _)) => Ok(val),
+            Err(err @ Interrupt::Error(_)) => Err(err),
+            // loop control must not leak into the caller's loops
+            Err(interrupt) => Err(interrupt.into_error().into()),
         }
     }
 
-    pub const fn script(def: FnDef<'a>, hash: u64) -> Self {
-        Self::Script(ScriptFn::new(def, hash))
+    pub const fn closure(
+        expr: ClosureExpr,
+        captured: Vec<(String, Value)>,
+        home: Option<ModuleId>,
+    ) -> Self {
+        Self::Closure(ClosureFn::new(expr, captured, home))
+    }
+
+    pub const fn script(def: FnDef, hash: u64, home: Option<ModuleId>) -> Self {
+        Self::Script(ScriptFn::new(def, hash, home))
+    }
+}
+
+impl Display for Callable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Native(native_fn) => Display::fmt(native_fn, f),
+            Self::Script(script_fn) => Display::fmt(script_fn, f),
+            Self::Closure(closure) => Display::fmt(closure, f),
+        }
     }
 }
 
-impl From<NativeFn> for Callable<'_> {
+impl From<NativeFn> for Callable {
     fn from(native_fn: NativeFn) -> Self {
         Self::Native(native_fn)
     }
 }
 
-impl<'a> From<ScriptFn<'a>> for Callable<'a> {
-    fn from(script_fn: ScriptFn<'a>) -> Self {
+impl From<ScriptFn> for Callable {
+    fn from(script_fn: ScriptFn) -> Self {
         Self::Script(script_fn)
     }
 }
@@ -2,21 +2,26 @@ use std::fmt::Display;
 
 use parser::ast::FnDef;
 
+use crate::ModuleId;
+
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct ScriptFn<'a> {
-    pub def: FnDef<'a>,
+pub struct ScriptFn {
+    pub def: FnDef,
     pub hash: u64,
+    /// Module the function is defined in, whose globals it sees. `None` for
+    /// the scripts run at top level.
+    pub home: Option<ModuleId>,
 }
 
-impl<'a> ScriptFn<'a> {
+impl ScriptFn {
     #[must_use]
-    pub const fn new(def: FnDef<'a>, hash: u64) -> Self {
-        Self { def, hash }
+    pub const fn new(def: FnDef, hash: u64, home: Option<ModuleId>) -> Self {
+        Self { def, hash, home }
     }
 }
 
-impl Display for ScriptFn<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Display for ScriptFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "ScriptFn({})", self.def)
     }
 }
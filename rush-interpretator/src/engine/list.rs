@@ -0,0 +1,87 @@
+use std::fmt;
+
+use super::Visit;
+use crate::{Locked, RuntimeError, RuntimeResult, Shared, Value};
+
+/// List of values shared between every copy of it, so that
+/// `let ys = xs; ys[0] = 1;` also changes `xs`.
+#[must_use]
+#[derive(Debug, Clone)]
+pub struct List(Shared<Locked<Vec<Value>>>);
+
+impl List {
+    pub fn new(items: Vec<Value>) -> Self {
+        Self(items.into())
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.get().len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.get().is_empty()
+    }
+
+    pub fn get(&self, index: i64) -> RuntimeResult<Value> {
+        let items = self.0.get();
+        Self::position(index, items.len()).map(|i| items[i].clone())
+    }
+
+    pub fn set(&self, index: i64, val: Value) -> RuntimeResult<()> {
+        let mut items = self.0.get_mut();
+        let i = Self::position(index, items.len())?;
+        items[i] = val;
+        drop(items);
+        Ok(())
+    }
+
+    pub fn push(&self, val: Value) {
+        self.0.get_mut().push(val);
+    }
+
+    /// Copy the items out, so they can be iterated without holding the lock.
+    #[must_use]
+    pub fn to_vec(&self) -> Vec<Value> {
+        self.0.get().clone()
+    }
+
+    fn position(index: i64, len: usize) -> RuntimeResult<usize> {
+        usize::try_from(index)
+            .ok()
+            .filter(|i| *i < len)
+            .ok_or(RuntimeError::IndexOutOfBounds { index, len })
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        // A list met again inside of itself is only equal to itself
+        self.0.ptr_eq(&other.0)
+            || Visit::enter(&[self.0.as_ptr(), other.0.as_ptr()])
+                .is_some_and(|_visit| *self.0.get() == *other.0.get())
+    }
+}
+
+impl From<Vec<Value>> for List {
+    fn from(items: Vec<Value>) -> Self {
+        Self::new(items)
+    }
+}
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(_visit) = Visit::enter(&[self.0.as_ptr()]) else {
+            return write!(f, "[...]");
+        };
+        write!(f, "[")?;
+        for (i, item) in self.0.get().iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{item}")?;
+        }
+        write!(f, "]")
+    }
+}
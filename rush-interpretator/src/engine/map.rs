@@ -0,0 +1,82 @@
+use std::{collections::BTreeMap, fmt};
+
+use super::Visit;
+use crate::{Locked, Shared, Value};
+
+/// String keyed map of values. Like [`List`](crate::List), every copy of a
+/// map refers to the same entries.
+#[must_use]
+#[derive(Debug, Clone)]
+pub struct Map(Shared<Locked<BTreeMap<String, Value>>>);
+
+impl Map {
+    pub fn new(entries: BTreeMap<String, Value>) -> Self {
+        Self(entries.into())
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.get().len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.get().is_empty()
+    }
+
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.0.get().get(key).cloned()
+    }
+
+    pub fn insert(&self, key: impl Into<String>, val: Value) -> Option<Value> {
+        self.0.get_mut().insert(key.into(), val)
+    }
+
+    #[allow(clippy::must_use_candidate)]
+    pub fn remove(&self, key: &str) -> Option<Value> {
+        self.0.get_mut().remove(key)
+    }
+
+    #[must_use]
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.0.get().contains_key(key)
+    }
+
+    /// Copy the entries out, so they can be iterated without holding the lock.
+    #[must_use]
+    pub fn to_map(&self) -> BTreeMap<String, Value> {
+        self.0.get().clone()
+    }
+}
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        // Like lists, a map met again inside of itself is only equal to itself
+        self.0.ptr_eq(&other.0)
+            || Visit::enter(&[self.0.as_ptr(), other.0.as_ptr()])
+                .is_some_and(|_visit| *self.0.get() == *other.0.get())
+    }
+}
+
+impl From<BTreeMap<String, Value>> for Map {
+    fn from(entries: BTreeMap<String, Value>) -> Self {
+        Self::new(entries)
+    }
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(_visit) = Visit::enter(&[self.0.as_ptr()]) else {
+            return write!(f, "#{{...}}");
+        };
+        write!(f, "#{{")?;
+        for (i, (key, val)) in self.0.get().iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{key}: {val}")?;
+        }
+        write!(f, "}}")
+    }
+}
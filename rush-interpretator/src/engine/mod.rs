@@ -1,28 +1,113 @@
-use std::{collections::HashMap, process::Command};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::SystemTime,
+};
 
 use parser::{
-    ast::{BinOpExpr, Block, Expr, ExprKind, FnCall, If, Item, ItemKind, UnOpKind, While},
-    parse,
+    ast::{
+        BinOpExpr, Block, EnvAssign, Exec, Expr, ExprKind, FnCall, For, If, IndexExpr, Item,
+        ItemKind, RangeExpr, TemplatePart, Tree, UnOpKind, While, WithEnv, Within,
+    },
+    parse_recovering, FileId, SourceMap,
 };
 
-use crate::{CommandError, CommandResult, Error, Result, RuntimeError, RuntimeResult};
+use crate::{Error, Result, RuntimeError, RuntimeResult};
+
+mod_use::mod_use![
+    value, utils, scope, var, refs, func, module, range, flow, list, map, exec, config, trace,
+    pipeline, env, session, traceback, arith
+];
 
-mod_use::mod_use![value, utils, scope, var, refs, func, module];
+#[cfg(test)]
+mod test;
 
-const MAX_DEPTH: usize = 1 << 14;
+/// Most scopes, of function calls and blocks, alive at once. Looking a name up
+/// goes through all of them, so runaway recursion must end early.
+const MAX_DEPTH: usize = 1 << 12;
+/// When less native stack than this is left, evaluation goes on in a new
+/// segment of [`STACK_SEGMENT`] bytes, so that deep recursion stops at
+/// [`MAX_DEPTH`] instead of overflowing the stack of the thread.
+const STACK_RED_ZONE: usize = 256 * 1024;
+const STACK_SEGMENT: usize = 4 * 1024 * 1024;
 
 #[must_use]
 pub struct Engine {
-    fns: HashMap<String, Box<dyn ExternalFn>>,
+    fns: HashMap<String, NativeFn>,
+    modules: Vec<Module>,
+    config: Config,
+    env: Env,
+    cwd: Option<PathBuf>,
 }
 
 impl Engine {
+    /// New engine, starting with a copy of the env of the host process.
     pub fn new() -> Self {
         Self {
             fns: HashMap::new(),
+            modules: Vec::new(),
+            config: Config::default(),
+            env: Env::from_process(),
+            cwd: None,
         }
     }
 
+    /// Directory scripts start in, the cwd of the host process by default.
+    /// Relative paths are resolved against the cwd of the host process.
+    pub fn with_cwd(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(dir.into());
+        self
+    }
+
+    /// Replace the env scripts start with.
+    pub fn with_env(mut self, env: Env) -> Self {
+        self.env = env;
+        self
+    }
+
+    pub fn with_env_var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.set(name, value);
+        self
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// See [`Config::fail_on_nonzero`]
+    pub const fn fail_on_nonzero(mut self, enable: bool) -> Self {
+        self.config.fail_on_nonzero = enable;
+        self
+    }
+
+    /// See [`Config::shell`]
+    pub const fn use_shell(mut self, enable: bool) -> Self {
+        self.config.shell = enable;
+        self
+    }
+
+    /// See [`Config::overflow`]
+    pub const fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.config.overflow = overflow;
+        self
+    }
+
+    /// Trace every command to `sink` before running it. See [`Config::trace`]
+    pub fn with_trace(mut self, sink: impl TraceSink + 'static) -> Self {
+        self.config.trace = Some(Rc::new(sink));
+        self
+    }
+
+    /// Load the modules scripts `use` with `loader`. See
+    /// [`Config::module_loader`]
+    pub fn with_module_loader(mut self, loader: impl ModuleLoader + 'static) -> Self {
+        self.config.module_loader = Rc::new(loader);
+        self
+    }
+
     pub fn with_fn<Param, FnPtr, Func>(self, name: impl Into<String>, func: Func) -> Self
     where
         Func: Into<ExtractFn<Param, FnPtr>>,
@@ -32,32 +117,63 @@ impl Engine {
     }
 
     pub fn with_fn_raw(mut self, name: impl Into<String>, func: impl ExternalFn) -> Self {
-        self.fns.insert(name.into(), Box::new(func));
+        let name = name.into();
+        self.fns.insert(name.clone(), NativeFn::new(func, name));
         self
     }
 
-    pub fn execute(self, src: &str) -> Result<'_, ()> {
-        let tree = parse(src).map_err(Error::Parse)?;
-        let mut ctx = Context::new();
+    /// Register a native function that can call function values passed to it
+    /// through [`Context::call_value`].
+    pub fn with_ctx_fn<Func>(mut self, name: impl Into<String>, func: Func) -> Self
+    where
+        Func: Fn(&mut Context, FnCallArg) -> Result<Value> + 'static,
+    {
+        let name = name.into();
+        self.fns
+            .insert(name.clone(), NativeFn::new_with_ctx(func, name));
+        self
+    }
 
-        let global = ctx.global();
+    /// Register a native module, whose functions and constants scripts reach
+    /// as `name::member`. See [`Module`]
+    pub fn with_module(mut self, module: Module) -> Self {
+        self.modules.push(module);
+        self
+    }
+
+    pub fn execute(self, src: &str) -> Result<()> {
+        self.execute_named("<script>", src)
+    }
 
-        for (name, func) in self.fns {
-            global.register_boxed_external_fn(name, func);
+    /// Like [`execute`](Self::execute), with `name` as the file name of the
+    /// script in errors and traces.
+    pub fn execute_named(self, name: impl Into<String>, src: &str) -> Result<()> {
+        let mut ctx = self.into_context()?;
+        let tree = ctx.load(name, src)?;
+        drop(ctx.run(&tree)?);
+        Ok(())
+    }
+
+    /// Context with the functions, config, env and cwd of the engine, which
+    /// can [`run`](Context::run) any number of scripts one after another.
+    pub fn into_context(self) -> Result<Context> {
+        let mut ctx = Context::with_config(self.config);
+        ctx.env = self.env;
+        if let Some(cwd) = self.cwd {
+            ctx.set_cwd(cwd)?;
         }
 
-        // hoist
-        for item in &tree.items {
-            if let ItemKind::FnDef(fn_def) = &item.kind {
-                ctx.current_mut().register_script_fn(fn_def.clone());
-            }
+        let global = ctx.global();
+
+        for func in self.fns.into_values() {
+            global.register_native_fn(func);
         }
 
-        for item in &tree.items {
-            drop(ctx.eval_item(item)?);
+        for module in self.modules {
+            ctx.modules.add_native(module);
         }
 
-        Ok(())
+        Ok(ctx)
     }
 }
 
@@ -68,31 +184,186 @@ impl Default for Engine {
 }
 
 #[must_use]
-pub struct Context<'src> {
-    scopes: Vec<Scope<'src>>,
+pub struct Context {
+    scopes: Vec<Scope>,
     depth: usize,
+    config: Config,
+    env: Env,
+    cwd: PathBuf,
+    sources: SourceMap,
+    /// Where each loaded file is, as resolved when it was loaded
+    paths: HashMap<FileId, PathBuf>,
+    modules: Modules,
+    /// Module whose code is running, `None` for the scripts run at top level
+    home: Option<ModuleId>,
 }
 
-impl<'src> Context<'src> {
+impl Context {
     pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Self {
         let mut scopes = Vec::with_capacity(8);
         scopes.push(Scope::new_global());
-        Self { scopes, depth: 0 }
+        Self {
+            scopes,
+            depth: 0,
+            config,
+            env: Env::from_process(),
+            cwd: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            sources: SourceMap::new(),
+            paths: HashMap::new(),
+            modules: Modules::default(),
+            home: None,
+        }
+    }
+
+    /// Parse `src`, keeping it in the [`SourceMap`] as `name` so spans of the
+    /// tree can be resolved back to it. Fails with every syntax error in
+    /// `src`.
+    ///
+    /// `name` is the path of the script, relative to the current cwd. Modules
+    /// it imports are looked up from there, even after a `cd`.
+    pub fn load(&mut self, name: impl Into<String>, src: impl Into<String>) -> Result<Tree> {
+        let name = name.into();
+        let path = self.cwd.join(&name);
+        let path = path.canonicalize().unwrap_or(path);
+        let file = self.sources.add(name, src);
+        self.paths.insert(file, path);
+        let src = &self.sources.get(file).expect("File was just added").src;
+        parse_recovering(src, file)
+            .into_result()
+            .map_err(Error::Parse)
+    }
+
+    /// Sources of every script loaded into the context.
+    #[must_use]
+    pub const fn sources(&self) -> &SourceMap {
+        &self.sources
+    }
+
+    /// Run a script at top level, returning the value of its trailing
+    /// expression. Variables and functions it defines are kept for the
+    /// scripts run after it.
+    pub fn run(&mut self, tree: &Tree) -> Result<Value> {
+        match self.eval_tree(tree) {
+            // `return` at top level ends the script
+            Ok(val) | Err(Interrupt::Return(val, _)) => Ok(val),
+            Err(interrupt) => Err(interrupt.into_error()),
+        }
+    }
+
+    /// Evaluate a script or module in the global scope of [`home`](Self::home).
+    fn eval_tree(&mut self, tree: &Tree) -> EvalResult {
+        // hoist
+        let home = self.home;
+        for item in &tree.items {
+            if let ItemKind::FnDef(fn_def) = &item.kind {
+                self.global().register_script_fn(fn_def.clone(), home);
+            }
+        }
+
+        tree.items
+            .iter()
+            .try_for_each(|item| self.eval_item(item).map(drop))
+            .and_then(|()| {
+                tree.expr
+                    .as_ref()
+                    .map_or(Ok(Value::Unit), |expr| self.eval_expr(expr))
+            })
+    }
+
+    /// Value of a global variable or function.
+    #[must_use]
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.scopes[0].get(name).ok().map(Variable::value)
+    }
+
+    /// Define or overwrite a global variable.
+    pub fn set_global(&mut self, name: impl Into<String>, value: impl Into<Value>) {
+        self.scopes[0].new_var(name, value);
+    }
+
+    /// Directory commands are run in
+    #[must_use]
+    pub fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    /// Change the directory commands are run in, like `cd`. Relative paths are
+    /// resolved against the current one.
+    pub fn set_cwd(&mut self, dir: impl AsRef<Path>) -> RuntimeResult<()> {
+        let dir = dir.as_ref();
+        let not_a_dir = |reason: String| RuntimeError::ChangeDir {
+            path: dir.display().to_string(),
+            reason,
+        };
+        let new = self
+            .cwd
+            .join(dir)
+            .canonicalize()
+            .map_err(|err| not_a_dir(err.to_string()))?;
+        if !new.is_dir() {
+            return Err(not_a_dir("Not a directory".to_owned()));
+        }
+        self.cwd = new;
+        Ok(())
+    }
+
+    /// Env vars commands are run with
+    #[must_use]
+    pub const fn env(&self) -> &Env {
+        &self.env
     }
 
-    fn eval_item(&mut self, item: &Item<'src>) -> Result<'src, Value> {
+    pub const fn env_mut(&mut self) -> &mut Env {
+        &mut self.env
+    }
+
+    fn eval_item(&mut self, item: &Item) -> EvalResult {
+        self.eval_item_kind(item)
+            .map_err(|interrupt| interrupt.with_span(item.span))
+    }
+
+    fn eval_item_kind(&mut self, item: &Item) -> EvalResult {
         match &item.kind {
             ItemKind::FnDef(_) => Ok(Value::Unit),
             ItemKind::Stmt(stmt) => {
                 let val = self.eval_expr(&stmt.expr)?;
-                self.current_mut().new_var(stmt.ident.name, val);
+                self.current_mut().new_var(&stmt.ident.name, val);
                 Ok(Value::Unit)
             }
             ItemKind::Expr(expr) => self.eval_expr(expr),
+            ItemKind::Return(ret) => {
+                let val = match &ret.expr {
+                    Some(expr) => self.eval_expr(expr)?,
+                    None => Value::Unit,
+                };
+                Err(Interrupt::Return(val, item.span))
+            }
+            ItemKind::Break(brk) => {
+                let val = match &brk.expr {
+                    Some(expr) => self.eval_expr(expr)?,
+                    None => Value::Unit,
+                };
+                Err(Interrupt::Break(val, item.span))
+            }
+            ItemKind::Continue(_) => Err(Interrupt::Continue(item.span)),
+            ItemKind::Use(use_item) => self.eval_use(use_item),
             ItemKind::Assign(assign) => {
                 let new_val = self.eval_expr(&assign.expr)?;
-                let var = self.search_mut(assign.ident.name)?;
-                var.update(new_val);
+                let Some((last, indices)) = assign.indices.split_last() else {
+                    self.search_mut(&assign.ident.name)?.update(new_val);
+                    return Ok(Value::Unit);
+                };
+                let mut container = self.search(&assign.ident.name)?.value();
+                for index in indices {
+                    let index = self.eval_expr(index)?;
+                    container = Self::index(container, index)?;
+                }
+                let index = self.eval_expr(last)?;
+                Self::set_index(container, index, new_val)?;
                 Ok(Value::Unit)
             }
             ItemKind::If(If {
@@ -109,20 +380,138 @@ impl<'src> Context<'src> {
                     Ok(Value::Unit)
                 }
             }
+            ItemKind::EnvAssign(EnvAssign { ident, expr, .. }) => {
+                match self.eval_expr(expr)? {
+                    Value::Unit => drop(self.env.remove(&ident.name)),
+                    val => self.env.set(&ident.name, val.to_string()),
+                }
+                Ok(Value::Unit)
+            }
+            ItemKind::WithEnv(with_env) => self.eval_with_env(with_env),
+            ItemKind::Within(within) => self.eval_within(within),
             ItemKind::While(While { expr, block, .. }) => {
                 while self.eval_expr(expr)?.rt_cast::<bool>("<while_cond>")? {
-                    drop(self.eval_block(block)?);
+                    let res = self.eval_block(block);
+                    if let ControlFlow::Break(val) = Self::loop_flow(res)? {
+                        return Ok(val);
+                    }
                 }
                 Ok(Value::Unit)
             }
-            ItemKind::For(_) => {
-                unimplemented!("for loop")
+            ItemKind::For(For {
+                ident, expr, block, ..
+            }) => {
+                let iter = self.eval_expr(expr)?.rt_iter("<for_iter>")?;
+                for val in iter {
+                    self.enter_scope("for")?;
+                    self.current_mut().new_var(&ident.name, val);
+                    let res = self.eval_block(block);
+                    self.pop_scope();
+                    if let ControlFlow::Break(val) = Self::loop_flow(res)? {
+                        return Ok(val);
+                    }
+                }
+                Ok(Value::Unit)
             }
             _ => unreachable!("Break by new variant"),
         }
     }
 
-    fn eval_expr(&mut self, expr: &Expr<'src>) -> Result<'src, Value> {
+    /// Render interpolated text.
+    fn eval_template(&mut self, parts: &[TemplatePart]) -> EvalResult<String> {
+        let mut out = String::new();
+        for part in parts {
+            match part {
+                TemplatePart::Text(text) => out.push_str(text),
+                TemplatePart::Interp(expr) | TemplatePart::RawInterp(expr) => {
+                    out.push_str(&self.eval_expr(expr)?.to_string());
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Evaluate the interpolations of a command. `${expr}` values are kept so
+    /// that they end up as exactly one word (or one word per item, for lists),
+    /// `$!{expr}` becomes part of the command text.
+    fn eval_command(&mut self, parts: &[TemplatePart]) -> EvalResult<Vec<CmdPiece>> {
+        parts
+            .iter()
+            .map(|part| {
+                Ok(match part {
+                    TemplatePart::Text(text) => CmdPiece::Text(text.clone()),
+                    TemplatePart::Interp(expr) => CmdPiece::Value(self.eval_expr(expr)?),
+                    TemplatePart::RawInterp(expr) => {
+                        CmdPiece::Text(self.eval_expr(expr)?.to_string())
+                    }
+                })
+            })
+            .collect()
+    }
+
+    fn trace_exec(&self, exec: &Exec, cmd: &str) {
+        if let Some(sink) = &self.config.trace {
+            let location = self.sources.location(exec.span);
+            sink.trace(&TraceEvent {
+                time: SystemTime::now(),
+                cmd,
+                file: location.name,
+                line: location.line,
+                col: location.col,
+            });
+        }
+    }
+
+    /// Run the block with the env vars set, restoring them however it exits.
+    /// Other vars the block sets are kept.
+    fn eval_with_env(&mut self, WithEnv { vars, block, .. }: &WithEnv) -> EvalResult {
+        let vars = self.eval_expr(vars)?.rt_cast::<Map>("<with_env>")?;
+        let mut saved = Vec::new();
+        for (name, val) in vars.to_map() {
+            saved.push((name.clone(), self.env.get(&name).map(str::to_owned)));
+            match val {
+                Value::Unit => drop(self.env.remove(&name)),
+                val => self.env.set(name, val.to_string()),
+            }
+        }
+        let res = self.eval_block(block);
+        for (name, val) in saved {
+            match val {
+                Some(val) => self.env.set(name, val),
+                None => drop(self.env.remove(&name)),
+            }
+        }
+        res
+    }
+
+    /// Run the block in `dir`, changing back however it exits.
+    fn eval_within(&mut self, Within { dir, block, .. }: &Within) -> EvalResult {
+        let dir = self.eval_expr(dir)?.to_string();
+        let saved = self.cwd.clone();
+        self.set_cwd(dir)?;
+        let res = self.eval_block(block);
+        self.cwd = saved;
+        res
+    }
+
+    /// Decide whether a loop goes on after one evaluation of its body.
+    fn loop_flow(res: EvalResult) -> EvalResult<ControlFlow<Value>> {
+        match res {
+            Ok(_) | Err(Interrupt::Continue(_)) => Ok(ControlFlow::Continue(())),
+            Err(Interrupt::Break(val, _)) => Ok(ControlFlow::Break(val)),
+            Err(interrupt) => Err(interrupt),
+        }
+    }
+
+    fn eval_expr(&mut self, expr: &Expr) -> EvalResult {
+        // Every call and nested block goes through here
+        stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, || {
+            self.eval_expr_kind(expr)
+                .map_err(|interrupt| interrupt.with_span(expr.span))
+        })
+    }
+
+    fn eval_expr_kind(&mut self, expr: &Expr) -> EvalResult {
         match &expr.kind {
             ExprKind::Unit => Ok(Value::Unit),
             ExprKind::Literal(lit) => Value::from(lit).ok(),
@@ -130,18 +519,76 @@ impl<'src> Context<'src> {
             ExprKind::Block(block) => self.eval_block(block),
             ExprKind::BinOp(op) => self.eval_bin_op(op),
             ExprKind::Ident(ident) => self
-                .search(ident.name)
+                .search(&ident.name)
                 .map(Variable::value)
                 .map_err(Into::into),
-            ExprKind::Exec(cmd) => {
-                println!("Executing {cmd:?}");
-                let res = eval_exec_str(cmd.cmd)?;
-                Value::Str(res.into()).ok()
+            ExprKind::Exec(exec) => {
+                let pieces = self.eval_command(&exec.parts)?;
+                let cmd = CmdPiece::render(&pieces);
+                self.trace_exec(exec, &cmd);
+                let output = Pipeline::parse(pieces, self.config.shell, &self.env)?
+                    .run(&self.env, &self.cwd)?;
+                if exec.structured {
+                    return Ok(output_value(&output));
+                }
+                if self.config.fail_on_nonzero {
+                    check_status(&cmd, &output)?;
+                }
+                Value::from(String::from_utf8_lossy(&output.stdout).into_owned()).ok()
+            }
+            ExprKind::EnvVar(ident) => self
+                .env
+                .get(&ident.name)
+                .map_or(Value::Unit, |val| val.to_owned().into())
+                .ok(),
+            ExprKind::Template(template) => {
+                let s = self.eval_template(&template.parts)?;
+                Value::from(s).ok()
+            }
+            ExprKind::Range(RangeExpr { start, end, .. }) => {
+                let start = self.eval_expr(start)?.rt_cast::<i64>("<range_start>")?;
+                let end = self.eval_expr(end)?.rt_cast::<i64>("<range_end>")?;
+                Value::Range(Range::new(start, end)).ok()
+            }
+            ExprKind::List(list) => {
+                let items = list
+                    .items
+                    .iter()
+                    .map(|item| self.eval_expr(item))
+                    .collect::<EvalResult<Vec<_>>>()?;
+                Value::List(items.into()).ok()
+            }
+            ExprKind::Map(map) => {
+                let entries = map
+                    .entries
+                    .iter()
+                    .map(|entry| Ok((entry.key.clone(), self.eval_expr(&entry.expr)?)))
+                    .collect::<EvalResult<BTreeMap<_, _>>>()?;
+                Value::Map(entries.into()).ok()
+            }
+            ExprKind::Closure(closure) => {
+                let captured = self.scopes[1..=self.depth]
+                    .iter()
+                    .flat_map(Scope::vars)
+                    .map(|(name, var)| (name.to_owned(), var.value()))
+                    .collect();
+                let func = Callable::closure(closure.clone(), captured, self.home);
+                Value::Fn(func.into()).ok()
+            }
+            ExprKind::Index(IndexExpr { expr, index, .. }) => {
+                let container = self.eval_expr(expr)?;
+                let index = self.eval_expr(index)?;
+                Self::index(container, index).map_err(Into::into)
             }
             ExprKind::UnOp(op) => {
                 let val = self.eval_expr(&op.expr)?;
                 match op.kind {
-                    UnOpKind::Neg => Ok(Value::Int(-val.rt_cast::<i64>("<neg>")?)),
+                    UnOpKind::Neg => match val {
+                        Value::Float(val) => Ok(Value::Float(-val)),
+                        val => Ok(Value::Int(
+                            self.config.overflow.int_neg(val.rt_cast::<i64>("<neg>")?)?,
+                        )),
+                    },
                     UnOpKind::Not => Ok(Value::Bool(!val.rt_cast::<bool>("<not>")?)),
                     _ => unreachable!("Break by new variant"),
                 }
@@ -150,7 +597,39 @@ impl<'src> Context<'src> {
         }
     }
 
-    fn eval_bin_op(&mut self, bin_op: &BinOpExpr<'src>) -> Result<'src, Value> {
+    fn index(container: Value, index: Value) -> RuntimeResult<Value> {
+        match container {
+            Value::List(list) => list.get(index.rt_cast::<i64>("<index>")?),
+            Value::Map(map) => {
+                let key = index.rt_cast::<Shared<String>>("<key>")?;
+                map.get(&key)
+                    .ok_or_else(|| RuntimeError::KeyNotFound(key.to_string()))
+            }
+            other => Err(RuntimeError::TypeError {
+                ident: "<indexed>".to_owned(),
+                expected: "list or map".to_owned(),
+                found: other.type_name().to_owned(),
+            }),
+        }
+    }
+
+    fn set_index(container: Value, index: Value, val: Value) -> RuntimeResult<()> {
+        match container {
+            Value::List(list) => list.set(index.rt_cast::<i64>("<index>")?, val),
+            Value::Map(map) => {
+                let key = index.rt_cast::<Shared<String>>("<key>")?;
+                map.insert(key.to_string(), val);
+                Ok(())
+            }
+            other => Err(RuntimeError::TypeError {
+                ident: "<indexed>".to_owned(),
+                expected: "list or map".to_owned(),
+                found: other.type_name().to_owned(),
+            }),
+        }
+    }
+
+    fn eval_bin_op(&mut self, bin_op: &BinOpExpr) -> EvalResult {
         #[allow(clippy::enum_glob_use)]
         use parser::ast::BinOpKind::*;
 
@@ -160,23 +639,14 @@ impl<'src> Context<'src> {
 
         let (left, right) = (self.eval_expr(left)?, self.eval_expr(right)?);
         match kind {
-            numerical_op @ (Add | Sub | Mul | Div | Lt | Le | Gt | Ge) => {
-                let ty_name = left
-                    .ty_eq_name(&right)
-                    .ok_or_else(|| RuntimeError::TypeError {
-                        ident: format!("<right of ({})>", numerical_op.as_str()),
-                        expected: left.type_name().to_owned(),
-                        found: right.type_name().to_owned(),
-                    })?;
-
-                if ty_name == i64::TYPE_NAME {
-                    let (left, right) = (left.cast::<i64>().unwrap(), right.cast::<i64>().unwrap());
+            numerical_op @ (Add | Sub | Mul | Div | Rem | Pow | Lt | Le | Gt | Ge) => {
+                if let (Value::Int(left), Value::Int(right)) = (&left, &right) {
+                    let (left, right) = (*left, *right);
 
                     let res = match numerical_op {
-                        Add => Value::Int(left + right),
-                        Sub => Value::Int(left - right),
-                        Mul => Value::Int(left * right),
-                        Div => Value::Int(left / right),
+                        Add | Sub | Mul | Div | Rem | Pow => {
+                            Value::Int(self.config.overflow.int_op(numerical_op, left, right)?)
+                        }
                         Lt => Value::Bool(left < right),
                         Le => Value::Bool(left <= right),
                         Gt => Value::Bool(left > right),
@@ -184,14 +654,12 @@ impl<'src> Context<'src> {
                         _ => unreachable!("Break by new variant"),
                     };
                     Ok(res)
-                } else if ty_name == f64::TYPE_NAME {
-                    let (left, right) = (left.cast::<f64>().unwrap(), right.cast::<f64>().unwrap());
-
+                } else if let (Some(left), Some(right)) = (as_float(&left), as_float(&right)) {
+                    // Floats, or an int and a float
                     let res = match numerical_op {
-                        Add => Value::Float(left + right),
-                        Sub => Value::Float(left - right),
-                        Mul => Value::Float(left * right),
-                        Div => Value::Float(left / right),
+                        Add | Sub | Mul | Div | Rem | Pow => {
+                            Value::Float(float_op(numerical_op, left, right))
+                        }
                         Lt => Value::Bool(left < right),
                         Le => Value::Bool(left <= right),
                         Gt => Value::Bool(left > right),
@@ -200,21 +668,25 @@ impl<'src> Context<'src> {
                     };
                     Ok(res)
                 } else {
+                    let (side, found) = if as_float(&left).is_some() {
+                        ("right", right)
+                    } else {
+                        ("left", left)
+                    };
                     Err(RuntimeError::TypeError {
-                        ident: format!("<right of ({})>", numerical_op.as_str()),
-                        expected: left.type_name().to_owned(),
-                        found: right.type_name().to_owned(),
+                        ident: format!("<{side} of ({})>", numerical_op.as_str()),
+                        expected: "int or float".to_owned(),
+                        found: found.type_name().to_owned(),
                     })?
                 }
             }
             op @ (Eq | Neq) => {
-                if left.ty_eq(&right) {
-                    match op {
-                        Eq => Value::Bool(left == right),
-                        Neq => Value::Bool(left != right),
-                        _ => unreachable!("Break by new variant"),
-                    }
-                    .ok()
+                // `1 == 1.0`, as exact as the comparison of two floats
+                #[allow(clippy::float_cmp)]
+                let equal = if left.ty_eq(&right) {
+                    left == right
+                } else if let (Some(left), Some(right)) = (as_float(&left), as_float(&right)) {
+                    left == right
                 } else {
                     RuntimeError::TypeError {
                         ident: format!("<right of ({})>", op.as_str()),
@@ -222,7 +694,13 @@ impl<'src> Context<'src> {
                         found: right.type_name().to_owned(),
                     }
                     .err()?
+                };
+                match op {
+                    Eq => Value::Bool(equal),
+                    Neq => Value::Bool(!equal),
+                    _ => unreachable!("Break by new variant"),
                 }
+                .ok()
             }
             And => {
                 let left = left.rt_cast::<bool>("<left of (&&)>")?;
@@ -237,41 +715,87 @@ impl<'src> Context<'src> {
         }
     }
 
-    fn eval_fn(&mut self, fn_call: &FnCall<'src>) -> Result<'src, Value> {
-        let name = fn_call.ident.name;
-        let found = self.search_mut(name)?.value_ref();
-        let fn_ref = *found
+    fn eval_fn(&mut self, fn_call: &FnCall) -> EvalResult {
+        let name = &fn_call.ident.name;
+        let found = self.search(name)?.value_ref();
+        let func = found
             .cast_ref::<FnRef>()
             .map_err(|e| RuntimeError::TypeError {
-                ident: name.to_string(),
+                ident: name.clone(),
                 expected: FnRef::TYPE_NAME.to_owned(),
                 found: e.type_name().to_owned(),
-            })?;
-        self.get_fn(fn_ref)?.call(self, fn_call)
+            })?
+            .clone();
+        func.call(self, fn_call)
     }
 
-    fn eval_block(&mut self, block: &Block<'src>) -> Result<'src, Value> {
+    fn eval_block(&mut self, block: &Block) -> EvalResult {
         self.enter_scope("block")?;
+        let res = self.eval_block_items(block);
+        self.pop_scope();
+        res
+    }
+
+    /// Evaluate the items of `block` in the current scope.
+    ///
+    /// The value of a block is its trailing expression. A block ending with an
+    /// `if` or a loop item takes the value of that item, like a tail
+    /// expression in Rust.
+    fn eval_block_items(&mut self, block: &Block) -> EvalResult {
+        let mut last = Value::Unit;
         for item in &block.items {
-            drop(self.eval_item(item)?);
+            last = self.eval_item(item)?;
+        }
+        match (&block.expr, block.items.last()) {
+            (Some(expr), _) => self.eval_expr(expr),
+            (
+                None,
+                Some(Item {
+                    kind:
+                        ItemKind::If(_)
+                        | ItemKind::While(_)
+                        | ItemKind::For(_)
+                        | ItemKind::WithEnv(_)
+                        | ItemKind::Within(_),
+                    ..
+                }),
+            ) => Ok(last),
+            _ => Ok(Value::Unit),
+        }
+    }
+
+    /// Globals of the running script or module.
+    #[inline]
+    fn global(&mut self) -> &mut Scope {
+        match self.home {
+            Some(id) => &mut self.modules.get_mut(id).scope,
+            None => &mut self.scopes[0],
         }
-        self.pop_scope();
-        Ok(Value::Unit)
     }
 
     #[inline]
-    fn global(&mut self) -> &mut Scope<'src> {
-        &mut self.scopes[0]
+    fn global_ref(&self) -> &Scope {
+        self.home
+            .map_or(&self.scopes[0], |id| &self.modules.get(id).scope)
     }
 
     #[inline]
-    fn current(&self) -> &Scope<'src> {
-        &self.scopes[self.depth]
+    #[allow(dead_code)]
+    fn current(&self) -> &Scope {
+        if self.depth == 0 {
+            self.global_ref()
+        } else {
+            &self.scopes[self.depth]
+        }
     }
 
     #[inline]
-    fn current_mut(&mut self) -> &mut Scope<'src> {
-        &mut self.scopes[self.depth]
+    fn current_mut(&mut self) -> &mut Scope {
+        if self.depth == 0 {
+            self.global()
+        } else {
+            &mut self.scopes[self.depth]
+        }
     }
 
     #[inline]
@@ -295,60 +819,65 @@ impl<'src> Context<'src> {
     }
 
     #[inline]
-    fn pop_scope(&mut self) {
+    const fn pop_scope(&mut self) {
         self.depth -= 1;
     }
 
-    fn _get(&self, ref_: Ref) -> RuntimeResult<&Variable> {
-        for scope in self.scopes.iter().rev() {
-            if let Ok(val) = scope.search(&ref_) {
-                return Ok(val);
-            }
-        }
-        Err(RuntimeError::NullRefError(ref_))
+    /// Call a function value with already evaluated arguments.
+    pub fn call_value(&mut self, func: &Value, args: FnCallArg) -> Result<Value> {
+        let func = func.rt_cast_ref::<FnRef>("<callee>")?.clone();
+        func.call_values(self, func.name(), None, args)
+            .map_err(Interrupt::into_error)
     }
 
-    fn get_fn(&self, fn_ref: FnRef) -> RuntimeResult<Shared<Callable<'src>>> {
-        for scope in self.scopes.iter().rev() {
-            if let Ok(val) = scope.get_fn(fn_ref) {
-                return Ok(val);
-            }
-        }
-        Err(RuntimeError::NullRefError(fn_ref.inner()))
+    /// Scopes that are currently alive, innermost first: the local scopes,
+    /// then the globals of the running script or module. Scopes above `depth`
+    /// are kept around for reuse but must not be looked into.
+    #[inline]
+    fn live_scopes(&self) -> impl Iterator<Item = &Scope> {
+        self.scopes[1..=self.depth]
+            .iter()
+            .rev()
+            .chain(std::iter::once(self.global_ref()))
+    }
+
+    fn _get(&self, ref_: Ref) -> RuntimeResult<&Variable> {
+        self.live_scopes()
+            .find_map(|scope| scope.search(&ref_).ok())
+            .ok_or(RuntimeError::NullRefError(ref_))
     }
 
+    /// Find a variable by name, or a global of an imported module by
+    /// `module::name`.
     fn search(&self, name: &str) -> RuntimeResult<&Variable> {
-        self.scopes
-            .iter()
-            .rev()
-            .find_map(|x| x.get(name).ok())
-            .ok_or_else(|| RuntimeError::IdentNotFound(name.to_owned()))
+        let found = match name.split_once("::") {
+            Some((module, member)) => self
+                .imported(module)
+                .and_then(|module| module.scope.get(member).ok()),
+            None => self.live_scopes().find_map(|x| x.get(name).ok()),
+        };
+        found.ok_or_else(|| RuntimeError::IdentNotFound(name.to_owned()))
     }
 
     fn search_mut(&mut self, name: &str) -> RuntimeResult<&mut Variable> {
-        self.scopes
+        let (top_level, locals) = self.scopes[..=self.depth]
+            .split_first_mut()
+            .expect("Global scope should always be there");
+        let global = match self.home {
+            Some(id) => &mut self.modules.get_mut(id).scope,
+            None => top_level,
+        };
+        locals
             .iter_mut()
             .rev()
+            .chain(std::iter::once(global))
             .find_map(|x| x.get_mut(name).ok())
             .ok_or_else(|| RuntimeError::IdentNotFound(name.to_owned()))
     }
 }
 
-impl<'a> Default for Context<'a> {
+impl Default for Context {
     fn default() -> Self {
         Self::new()
     }
 }
-
-pub fn eval_exec(command: &str) -> CommandResult {
-    Command::new("sh")
-        .arg("-c")
-        .arg(command)
-        .output()
-        .map_err(CommandError::Command)
-}
-
-pub fn eval_exec_str(command: &str) -> CommandResult<String> {
-    let res = eval_exec(command)?;
-    Ok(String::from_utf8_lossy(&res.stdout).to_string())
-}
@@ -0,0 +1,125 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{RuntimeError, RuntimeResult};
+
+/// Extension of module files
+const EXTENSION: &str = "rush";
+
+/// Where the source of modules imported by `use` comes from.
+///
+/// A module is identified by the path it resolves to: it is evaluated the
+/// first time that path is imported, and later imports of the same path get
+/// the same module. The path is also the name of the module in errors.
+pub trait ModuleLoader {
+    /// Path of the module imported as `name` by the script at `importer`.
+    /// Scripts that are not read from a file, like the REPL, are taken to be
+    /// in the cwd.
+    fn resolve(&self, name: &str, importer: &Path) -> RuntimeResult<PathBuf>;
+
+    /// Source of the module at `path`, as given by
+    /// [`resolve`](Self::resolve).
+    fn read(&self, path: &Path) -> RuntimeResult<String>;
+}
+
+/// Loads `name.rush` next to the importing script, or else from the first
+/// directory of the search path that has it. This is the default loader.
+#[derive(Debug, Clone, Default)]
+pub struct FileLoader {
+    dirs: Vec<PathBuf>,
+}
+
+impl FileLoader {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look for modules in `dir` too, after the directories added before.
+    /// Relative paths are resolved against the cwd of the host process.
+    #[must_use]
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dirs.push(dir.into());
+        self
+    }
+}
+
+impl ModuleLoader for FileLoader {
+    fn resolve(&self, name: &str, importer: &Path) -> RuntimeResult<PathBuf> {
+        let file_name = Path::new(name).with_extension(EXTENSION);
+        let mut searched: Vec<PathBuf> = vec![];
+        for dir in importer
+            .parent()
+            .into_iter()
+            .chain(self.dirs.iter().map(PathBuf::as_path))
+        {
+            let path = dir.join(&file_name);
+            if !searched.contains(&path) {
+                searched.push(path);
+            }
+        }
+        searched
+            .iter()
+            .find(|path| path.is_file())
+            .and_then(|path| path.canonicalize().ok())
+            .ok_or_else(|| RuntimeError::ModuleNotFound {
+                name: name.to_owned(),
+                searched,
+            })
+    }
+
+    fn read(&self, path: &Path) -> RuntimeResult<String> {
+        std::fs::read_to_string(path).map_err(|err| RuntimeError::ModuleLoad {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        })
+    }
+}
+
+/// Modules kept in memory by name, e.g. bundled with `include_str!`. Each one
+/// resolves to the path `name.rush`, wherever it is imported from.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+    modules: HashMap<PathBuf, String>,
+}
+
+impl MemoryLoader {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the module `name`, replacing any other of the same name.
+    #[must_use]
+    pub fn with_module(mut self, name: &str, src: impl Into<String>) -> Self {
+        self.modules
+            .insert(Path::new(name).with_extension(EXTENSION), src.into());
+        self
+    }
+}
+
+impl ModuleLoader for MemoryLoader {
+    fn resolve(&self, name: &str, _: &Path) -> RuntimeResult<PathBuf> {
+        let path = Path::new(name).with_extension(EXTENSION);
+        if self.modules.contains_key(&path) {
+            Ok(path)
+        } else {
+            Err(RuntimeError::ModuleNotFound {
+                name: name.to_owned(),
+                searched: vec![path],
+            })
+        }
+    }
+
+    fn read(&self, path: &Path) -> RuntimeResult<String> {
+        self.modules
+            .get(path)
+            .cloned()
+            .ok_or_else(|| RuntimeError::ModuleLoad {
+                path: path.display().to_string(),
+                reason: "No such module".to_owned(),
+            })
+    }
+}
@@ -1,9 +1,236 @@
-use std::collections::HashMap;
+mod_use::mod_use![loader];
 
-use crate::{Callable, FnRef, Shared, Variable};
+#[cfg(test)]
+mod test;
 
-pub struct Module<'a> {
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use parser::{ast::Use, FileId};
+
+use crate::{
+    Context, EvalResult, ExternalFn, ExtractFn, FnCallArg, Frame, FrameKind, Interrupt, NativeFn,
+    Result, RuntimeError, RuntimeResult, Scope, Value,
+};
+
+/// Index of a module in the [`Context`] that loaded it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModuleId(usize);
+
+/// Globals reachable from scripts as `name::global`.
+///
+/// A module is either a script loaded by `use`, which is evaluated once in a
+/// global scope of its own, or a native module of functions and constants,
+/// built with [`Module::new`] and registered with
+/// [`Engine::with_module`](crate::Engine::with_module). Native modules are
+/// reachable from every script without `use`:
+///
+/// ```text
+/// let longest = math::max(len(a), len(b));
+/// ```
+#[must_use]
+pub struct Module {
     pub name: String,
-    pub fns: HashMap<FnRef, Shared<Callable<'a>>>,
-    pub vars: Vec<Variable>,
+    /// File the module was loaded from, `None` for native modules
+    pub path: Option<PathBuf>,
+    /// Globals of the module. Those of a script include the native functions
+    /// of the engine.
+    pub scope: Scope,
+    /// Modules imported by the module, by the name they are imported as
+    imports: HashMap<String, ModuleId>,
+}
+
+impl Module {
+    /// New native module, without any function or constant.
+    pub fn new(name: impl Into<String>) -> Self {
+        let name = name.into();
+        Self {
+            scope: Scope::new(format!("module {name}")),
+            name,
+            path: None,
+            imports: HashMap::new(),
+        }
+    }
+
+    /// See [`Engine::with_fn`](crate::Engine::with_fn)
+    pub fn with_fn<Param, FnPtr, Func>(self, name: impl Into<String>, func: Func) -> Self
+    where
+        Func: Into<ExtractFn<Param, FnPtr>>,
+        ExtractFn<Param, FnPtr>: ExternalFn,
+    {
+        self.with_fn_raw(name, func.into())
+    }
+
+    /// See [`Engine::with_fn_raw`](crate::Engine::with_fn_raw)
+    pub fn with_fn_raw(mut self, name: impl Into<String>, func: impl ExternalFn) -> Self {
+        self.scope
+            .register_native_fn(NativeFn::new(func, name.into()));
+        self
+    }
+
+    /// See [`Engine::with_ctx_fn`](crate::Engine::with_ctx_fn)
+    pub fn with_ctx_fn<Func>(mut self, name: impl Into<String>, func: Func) -> Self
+    where
+        Func: Fn(&mut Context, FnCallArg) -> Result<Value> + 'static,
+    {
+        self.scope
+            .register_native_fn(NativeFn::new_with_ctx(func, name.into()));
+        self
+    }
+
+    /// Add a constant, reachable from scripts as `name::constant`.
+    pub fn with_const(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.scope.new_var(name, value);
+        self
+    }
+}
+
+/// Every module loaded into a [`Context`].
+#[derive(Default)]
+pub(super) struct Modules {
+    loaded: Vec<Module>,
+    by_path: HashMap<PathBuf, ModuleId>,
+    /// Native modules, reachable from every script without `use`
+    natives: HashMap<String, ModuleId>,
+    /// Modules imported by the scripts run at top level
+    imports: HashMap<String, ModuleId>,
+    /// Modules being evaluated, outermost first
+    loading: Vec<ModuleId>,
+}
+
+impl Modules {
+    pub(super) fn get(&self, id: ModuleId) -> &Module {
+        &self.loaded[id.0]
+    }
+
+    pub(super) fn get_mut(&mut self, id: ModuleId) -> &mut Module {
+        &mut self.loaded[id.0]
+    }
+
+    /// Add a native module, replacing any other of the same name.
+    pub(super) fn add_native(&mut self, module: Module) {
+        let id = ModuleId(self.loaded.len());
+        self.natives.insert(module.name.clone(), id);
+        self.loaded.push(module);
+    }
+
+    /// Drop the module `id`, which failed, along with the modules first
+    /// loaded while it ran. They are all loaded again by the next `use`.
+    fn unload(&mut self, id: ModuleId) {
+        self.loaded.truncate(id.0);
+        self.by_path.retain(|_, loaded| loaded.0 < id.0);
+    }
+
+    /// Modules imported by the module `home`, or by the top level scripts.
+    fn imports(&self, home: Option<ModuleId>) -> &HashMap<String, ModuleId> {
+        home.map_or(&self.imports, |id| &self.get(id).imports)
+    }
+
+    fn imports_mut(&mut self, home: Option<ModuleId>) -> &mut HashMap<String, ModuleId> {
+        match home {
+            Some(id) => &mut self.get_mut(id).imports,
+            None => &mut self.imports,
+        }
+    }
+
+    /// Names of the modules from the one at `path` to the last one being
+    /// loaded, if `path` is being loaded.
+    fn cycle(&self, path: &Path) -> Option<Vec<String>> {
+        let start = self
+            .loading
+            .iter()
+            .position(|id| self.get(*id).path.as_deref() == Some(path))?;
+        let mut names: Vec<_> = self.loading[start..]
+            .iter()
+            .map(|id| self.get(*id).name.clone())
+            .collect();
+        names.push(names[0].clone());
+        Some(names)
+    }
+}
+
+impl Context {
+    /// Evaluate `use name;`: load the module the first time it is imported,
+    /// then make it reachable as `name` from the running script or module.
+    /// Native modules are reachable already, importing them does nothing.
+    pub(super) fn eval_use(&mut self, Use { ident, span }: &Use) -> EvalResult {
+        if self.depth != 0 {
+            return Err(RuntimeError::NestedUse.into());
+        }
+        let name = &ident.name;
+        if self.modules.natives.contains_key(name) {
+            return Ok(Value::Unit);
+        }
+        let path = self.resolve_module(name, span.file)?;
+        if let Some(names) = self.modules.cycle(&path) {
+            return Err(RuntimeError::CircularImport(names).into());
+        }
+        let id = match self.modules.by_path.get(&path) {
+            Some(id) => *id,
+            None => self.load_module(name, path).map_err(|interrupt| {
+                interrupt.with_frame(Frame {
+                    name: format!("<module {name}>"),
+                    call_site: Some(*span),
+                    kind: FrameKind::Script,
+                })
+            })?,
+        };
+        self.modules.imports_mut(self.home).insert(name.clone(), id);
+        Ok(Value::Unit)
+    }
+
+    /// Module imported as `name` by the running script or module, or the
+    /// native module `name`.
+    pub(super) fn imported(&self, name: &str) -> Option<&Module> {
+        let id = self
+            .modules
+            .imports(self.home)
+            .get(name)
+            .or_else(|| self.modules.natives.get(name))?;
+        Some(self.modules.get(*id))
+    }
+
+    /// Path of the module `name` imported by the file `importer`, from the
+    /// [module loader](crate::Config::module_loader).
+    fn resolve_module(&self, name: &str, importer: FileId) -> RuntimeResult<PathBuf> {
+        let importer = self
+            .paths
+            .get(&importer)
+            .cloned()
+            .unwrap_or_else(|| self.cwd.clone());
+        self.config.module_loader.resolve(name, &importer)
+    }
+
+    /// Evaluate the module at `path` in a global scope of its own.
+    fn load_module(&mut self, name: &str, path: PathBuf) -> EvalResult<ModuleId> {
+        let src = self.config.module_loader.read(&path)?;
+        let tree = self.load(path.display().to_string(), src)?;
+
+        let id = ModuleId(self.modules.loaded.len());
+        self.modules.loaded.push(Module {
+            name: name.to_owned(),
+            path: Some(path.clone()),
+            scope: self.scopes[0].natives(format!("module {name}")),
+            imports: HashMap::new(),
+        });
+        self.modules.loading.push(id);
+        let home = self.home.replace(id);
+        let res = self.eval_tree(&tree);
+        self.home = home;
+        self.modules.loading.pop();
+
+        match res {
+            // `return` at top level ends the module
+            Ok(_) | Err(Interrupt::Return(..)) => {
+                self.modules.by_path.insert(path, id);
+                Ok(id)
+            }
+            Err(interrupt) => {
+                self.modules.unload(id);
+                Err(interrupt.into_error().into())
+            }
+        }
+    }
 }
@@ -0,0 +1,207 @@
+use std::{cell::Cell, rc::Rc};
+
+use crate::{Engine, Error, FileLoader, MemoryLoader, Module, RuntimeError, Session, Value};
+
+fn session(loader: MemoryLoader) -> Session {
+    Engine::new().with_module_loader(loader).session().unwrap()
+}
+
+fn runtime_error(res: crate::Result<Value>) -> RuntimeError {
+    match res {
+        Err(Error::Runtime { error, .. }) => *error,
+        other => panic!("Expected a runtime error, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_use() {
+    let mut session = session(MemoryLoader::new().with_module(
+        "util",
+        "let answer = 42; fn double(x) { helper(x) * 2 } fn helper(x) { x }",
+    ));
+    let val = session
+        .eval("use util; util::double(util::answer)")
+        .unwrap();
+    assert_eq!(val, Value::Int(84));
+    // Globals of a module are only reachable through its name
+    assert!(session.get("answer").is_none());
+    assert_eq!(
+        runtime_error(session.eval("helper(1)")).to_string(),
+        "Identifier `helper` not found"
+    );
+}
+
+#[test]
+fn test_module_globals() {
+    let mut session = session(MemoryLoader::new().with_module(
+        "counter",
+        "let n = 0; fn bump() { n = n + 1; n } fn apply(f) { f() }",
+    ));
+    drop(session.eval("use counter; let n = 10;").unwrap());
+    drop(session.eval("counter::bump(); counter::bump();").unwrap());
+    assert_eq!(session.eval("counter::n").unwrap(), Value::Int(2));
+    // A closure sees the globals of the script it's created in
+    assert_eq!(
+        session.eval("counter::apply(|| n)").unwrap(),
+        Value::Int(10)
+    );
+}
+
+#[test]
+fn test_evaluated_once() {
+    let loads = Rc::new(Cell::new(0));
+    let counted = loads.clone();
+    let loader = MemoryLoader::new()
+        .with_module("a", "use shared;")
+        .with_module("b", "use shared; let n = shared::n;")
+        .with_module("shared", "loaded(); let n = 1;");
+    let mut session = Engine::new()
+        .with_module_loader(loader)
+        .with_fn_raw("loaded", move |_| {
+            counted.set(counted.get() + 1);
+            Ok(Value::Unit)
+        })
+        .session()
+        .unwrap();
+    drop(
+        session
+            .eval("use a; use b; use shared; use shared;")
+            .unwrap(),
+    );
+    assert_eq!(loads.get(), 1);
+    assert_eq!(session.eval("b::n + shared::n").unwrap(), Value::Int(2));
+}
+
+#[test]
+fn test_circular_import() {
+    let mut session = session(
+        MemoryLoader::new()
+            .with_module("a", "use b;")
+            .with_module("b", "use c;")
+            .with_module("c", "use a;"),
+    );
+    let err = session.eval("use a;").unwrap_err();
+    assert_eq!(err.trace().len(), 3);
+    let RuntimeError::CircularImport(names) = runtime_error(Err(err)) else {
+        panic!("Expected a circular import");
+    };
+    assert_eq!(names, ["a", "b", "c", "a"]);
+    // Failed modules are not cached, importing one again fails the same way
+    assert!(matches!(
+        runtime_error(session.eval("use c;")),
+        RuntimeError::CircularImport(_)
+    ));
+}
+
+#[test]
+fn test_module_errors() {
+    let mut session = session(
+        MemoryLoader::new()
+            .with_module("broken", "let x = ;")
+            .with_module("failing", "use dep; let x = 1 + true;")
+            .with_module("dep", "let n = 1;"),
+    );
+    assert!(matches!(
+        runtime_error(session.eval("use missing;")),
+        RuntimeError::ModuleNotFound { name, .. } if name == "missing"
+    ));
+    assert!(matches!(session.eval("use broken;"), Err(Error::Parse(_))));
+    let err = session.eval("use failing;").unwrap_err();
+    assert_eq!(err.trace()[0].name, "<module failing>");
+    // A failed module is dropped, with the modules it loaded, and importing it
+    // again runs it again
+    assert!(matches!(
+        runtime_error(session.eval("use failing;")),
+        RuntimeError::TypeError { .. }
+    ));
+    assert_eq!(session.eval("use dep; dep::n").unwrap(), Value::Int(1));
+    assert!(matches!(
+        runtime_error(session.eval("if true { use failing; }")),
+        RuntimeError::NestedUse
+    ));
+}
+
+#[test]
+fn test_native_module() {
+    let math = Module::new("math")
+        .with_fn("max", |a: i64, b: i64| Ok(Value::Int(a.max(b))))
+        .with_const("answer", 42);
+    let calls = Rc::new(Cell::new(0));
+    let counted = calls.clone();
+    let host = Module::new("host")
+        .with_fn_raw("count", move |_| {
+            counted.set(counted.get() + 1);
+            Ok(Value::Int(counted.get()))
+        })
+        // Functions with the context can call back into the script
+        .with_ctx_fn("apply", |ctx, args| {
+            let [func, arg] = <[Value; 2]>::try_from(args).unwrap();
+            ctx.call_value(&func, vec![arg])
+        })
+        .with_const("name", "rush");
+    let mut session = Engine::new()
+        .with_module(math)
+        .with_module(host)
+        .with_module_loader(MemoryLoader::new().with_module("lib", "fn f() { math::answer }"))
+        .session()
+        .unwrap();
+    assert_eq!(session.eval("math::max(1, 2)").unwrap(), Value::Int(2));
+    // `use` of a native module does nothing, they are reachable from
+    // everywhere already
+    assert_eq!(
+        session
+            .eval("use math; use lib; math::max(lib::f(), 7)")
+            .unwrap(),
+        Value::Int(42)
+    );
+    assert_eq!(
+        session
+            .eval("host::count(); host::apply(|x| x * 2, host::count())")
+            .unwrap(),
+        Value::Int(4)
+    );
+    assert_eq!(calls.get(), 2);
+    assert_eq!(session.eval("host::name").unwrap(), Value::from("rush"));
+    // Members are only reachable through the name of their module
+    assert!(matches!(
+        runtime_error(session.eval("answer")),
+        RuntimeError::IdentNotFound(name) if name == "answer"
+    ));
+    assert!(matches!(
+        runtime_error(session.eval("math::min(1, 2)")),
+        RuntimeError::IdentNotFound(name) if name == "math::min"
+    ));
+}
+
+#[test]
+fn test_import_from_file() {
+    let dir = std::env::temp_dir().join(format!("rush-modules-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    std::fs::write(dir.join("helper.rush"), "use util; let n = util::answer;").unwrap();
+    std::fs::write(dir.join("util.rush"), "let answer = 42;").unwrap();
+    let mut session = Engine::new()
+        .with_builtins()
+        .with_module_loader(FileLoader::new())
+        .with_cwd(&dir)
+        .session()
+        .unwrap();
+
+    // Modules are looked up next to the importing script, wherever it has
+    // `cd`'d to since it was loaded
+    let ctx = session.context();
+    let tree = ctx.load("main.rush", "use helper; helper::n").unwrap();
+    ctx.set_cwd("/").unwrap();
+    assert_eq!(ctx.run(&tree).unwrap(), Value::Int(42));
+    session.context().set_cwd(&dir).unwrap();
+    assert_eq!(
+        session.eval(r#"cd("/"); use util; util::answer"#).unwrap(),
+        Value::Int(42)
+    );
+    assert!(matches!(
+        runtime_error(session.eval("use util;")),
+        RuntimeError::ModuleNotFound { name, .. } if name == "util"
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,765 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, PipeReader, PipeWriter, Read, Write},
+    iter::Peekable,
+    path::Path,
+    process::{Child, ChildStdin, Command, ExitStatus, Output, Stdio},
+    thread,
+};
+
+use crate::{shell_quote, shell_words, CommandError, CommandResult, Env, Value};
+
+#[cfg(test)]
+mod test;
+
+/// One piece of an evaluated ``$`cmd` ``: literal command text, or the value of
+/// a `${expr}`.
+#[derive(Debug, Clone)]
+pub enum CmdPiece {
+    Text(String),
+    Value(Value),
+}
+
+impl CmdPiece {
+    /// Render pieces into shell text, with values quoted so that the shell
+    /// reads them back as they are, inside quotes of the text or not.
+    #[must_use]
+    pub fn render(pieces: &[Self]) -> String {
+        let mut text = ShellText::new();
+        for piece in pieces {
+            match piece {
+                Self::Text(chars) => chars.chars().for_each(|c| text.push_char(c)),
+                Self::Value(val) => text.push_value(val),
+            }
+        }
+        text.finish()
+    }
+}
+
+/// Shell text being built, which keeps track of the quotes open at its end:
+/// a value must be quoted differently inside `'...'` or `"..."` than outside.
+#[derive(Debug)]
+struct ShellText {
+    text: String,
+    /// The quote char of the quoted string the text ends in, if any
+    quote: Option<char>,
+    /// The text ends with a `\` escaping whatever comes next. It's only
+    /// written out with the char it escapes, as there's nothing to escape in a
+    /// value.
+    escape: bool,
+}
+
+impl ShellText {
+    const fn new() -> Self {
+        Self {
+            text: String::new(),
+            quote: None,
+            escape: false,
+        }
+    }
+
+    fn push_char(&mut self, c: char) {
+        if std::mem::take(&mut self.escape) {
+            self.text.push('\\');
+            self.text.push(c);
+            return;
+        }
+        match (self.quote, c) {
+            (None | Some('"'), '\\') => {
+                self.escape = true;
+                return;
+            }
+            (None, '\'' | '"') => self.quote = Some(c),
+            (Some(quote), c) if c == quote => self.quote = None,
+            _ => {}
+        }
+        self.text.push(c);
+    }
+
+    fn push_str(&mut self, s: &str) {
+        s.chars().for_each(|c| self.push_char(c));
+    }
+
+    fn push_value(&mut self, val: &Value) {
+        self.escape = false;
+        match self.quote {
+            None => self.text.push_str(&shell_words(val)),
+            // Quoting only works outside of quotes, so close them around the
+            // value. It's part of the quoted word, so lists aren't split.
+            Some(quote) => {
+                self.text.push(quote);
+                self.text.push_str(&shell_quote(&val.to_string()));
+                self.text.push(quote);
+            }
+        }
+    }
+
+    /// Whether the next char is quoted or escaped, so it can't be an operator.
+    const fn is_quoted(&self) -> bool {
+        self.quote.is_some() || self.escape
+    }
+
+    fn at_word_start(&self) -> bool {
+        !self.is_quoted() && self.text.chars().last().is_none_or(char::is_whitespace)
+    }
+
+    fn finish(mut self) -> String {
+        if self.escape {
+            self.text.push('\\');
+        }
+        self.text
+    }
+}
+
+/// Standard stream of a process, as numbered in redirections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fd {
+    Stdout,
+    Stderr,
+}
+
+impl Fd {
+    const fn from_digit(c: char) -> Option<Self> {
+        match c {
+            '1' => Some(Self::Stdout),
+            '2' => Some(Self::Stderr),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Redirect {
+    /// `> path`, `2> path`, `>> path`
+    Out { fd: Fd, path: String, append: bool },
+    /// `2>&1`, `>&2`
+    Dup { fd: Fd, to: Fd },
+    /// `< path`
+    In(String),
+    /// `<<< ${value}`, feeds the value followed by a newline into stdin
+    HereString(String),
+}
+
+/// What a stage of a pipeline runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cmd {
+    /// Program and arguments, spawned directly
+    Argv(Vec<String>),
+    /// Shell text, run with `sh -c`
+    Shell(String),
+}
+
+/// A command of a pipeline, with the redirections taken out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stage {
+    pub cmd: Cmd,
+    pub redirects: Vec<Redirect>,
+}
+
+/// Commands connected with `|`. The engine sets up pipes and redirections
+/// itself.
+///
+/// By default every command is split into words and spawned without a shell:
+/// whitespace separates words, `'..'` and `"..."` quote, `\` escapes the next
+/// char (only some of them inside `"..."`), `$NAME` is replaced by the env var,
+/// and `${expr}` always becomes exactly one word (or one word per item, for
+/// lists) whatever it contains. With `shell`, each command is left to
+/// `sh -c` instead, and command lists (`;`, `&&`, `||`, `&` or newlines) are
+/// not split into stages: the whole command is run by the shell as it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+}
+
+enum Unit {
+    Char(char),
+    Value(Value),
+}
+
+/// Command being parsed, with the env to expand `$NAME` from.
+struct Units<'e> {
+    iter: Peekable<std::vec::IntoIter<Unit>>,
+    env: &'e Env,
+}
+
+impl Iterator for Units<'_> {
+    type Item = Unit;
+
+    fn next(&mut self) -> Option<Unit> {
+        self.iter.next()
+    }
+}
+
+impl Units<'_> {
+    fn next_if(&mut self, func: impl FnOnce(&Unit) -> bool) -> Option<Unit> {
+        self.iter.next_if(func)
+    }
+
+    /// After a `$`, read the name of an env var and return its value, empty if
+    /// it's not set. `None` if no name follows, so the `$` is literal.
+    fn env_var(&mut self) -> Option<String> {
+        let is_start =
+            |u: &Unit| matches!(u, Unit::Char(c) if c.is_ascii_alphabetic() || *c == '_');
+        let is_rest =
+            |u: &Unit| matches!(u, Unit::Char(c) if c.is_ascii_alphanumeric() || *c == '_');
+
+        let mut name = String::new();
+        let mut next = self.next_if(is_start);
+        while let Some(Unit::Char(c)) = next {
+            name.push(c);
+            next = self.next_if(is_rest);
+        }
+        if name.is_empty() {
+            return None;
+        }
+        Some(self.env.get(&name).unwrap_or_default().to_owned())
+    }
+}
+
+/// A stage being parsed.
+struct StageBuilder {
+    shell: bool,
+    /// Shell text, in shell mode
+    text: ShellText,
+    words: Vec<String>,
+    /// Word being read, `Some` even if it's empty so far (as in `''`)
+    word: Option<String>,
+    redirects: Vec<Redirect>,
+}
+
+impl StageBuilder {
+    const fn new(shell: bool) -> Self {
+        Self {
+            shell,
+            text: ShellText::new(),
+            words: vec![],
+            word: None,
+            redirects: vec![],
+        }
+    }
+
+    fn at_word_start(&self) -> bool {
+        if self.shell {
+            self.text.at_word_start()
+        } else {
+            self.word.is_none()
+        }
+    }
+
+    fn push_char(&mut self, c: char) {
+        if self.shell {
+            self.text.push_char(c);
+        } else if c.is_whitespace() {
+            self.end_word();
+        } else {
+            self.word.get_or_insert_default().push(c);
+        }
+    }
+
+    fn push_value(&mut self, val: &Value) {
+        if self.shell {
+            self.text.push_value(val);
+            return;
+        }
+        match val {
+            // Text right before or after the list sticks to its first or last item
+            Value::List(items) if !items.is_empty() => {
+                let items = items.to_vec();
+                let last = items.len() - 1;
+                for (i, item) in items.iter().enumerate() {
+                    self.word
+                        .get_or_insert_default()
+                        .push_str(&item.to_string());
+                    if i != last {
+                        self.end_word();
+                    }
+                }
+            }
+            Value::List(_) => {}
+            val => self.word.get_or_insert_default().push_str(&val.to_string()),
+        }
+    }
+
+    fn end_word(&mut self) {
+        self.words.extend(self.word.take());
+    }
+
+    fn finish(mut self) -> CommandResult<Stage> {
+        self.end_word();
+        let cmd = if self.shell {
+            let text = self.text.finish();
+            if text.trim().is_empty() {
+                return Err(empty_command());
+            }
+            Cmd::Shell(text)
+        } else {
+            if self.words.is_empty() {
+                return Err(empty_command());
+            }
+            Cmd::Argv(self.words)
+        };
+        Ok(Stage {
+            cmd,
+            redirects: self.redirects,
+        })
+    }
+}
+
+impl Pipeline {
+    /// Split the command into stages and redirections. Operators are only
+    /// recognized in unquoted command text, never in interpolated values.
+    pub fn parse(pieces: Vec<CmdPiece>, shell: bool, env: &Env) -> CommandResult<Self> {
+        let whole = CmdPiece::render(&pieces);
+        let iter = pieces
+            .into_iter()
+            .flat_map(|piece| match piece {
+                CmdPiece::Text(text) => text.chars().map(Unit::Char).collect::<Vec<_>>(),
+                CmdPiece::Value(val) => vec![Unit::Value(val)],
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .peekable();
+        let mut units = Units { iter, env };
+
+        let mut stages = vec![];
+        let mut stage = StageBuilder::new(shell);
+        // Depth of `(..)` and `$(..)` in shell mode, where nothing is an
+        // operator of ours
+        let mut subshell = 0usize;
+
+        while let Some(unit) = units.next() {
+            let c = match unit {
+                Unit::Value(val) => {
+                    stage.push_value(&val);
+                    continue;
+                }
+                Unit::Char(c) => c,
+            };
+            // Nothing quoted is an operator, the shell takes care of quotes
+            if shell && stage.text.is_quoted() {
+                stage.text.push_char(c);
+                continue;
+            }
+            let word_start = stage.at_word_start();
+            match c {
+                '\'' | '"' | '\\' if shell => stage.text.push_char(c),
+                // Line continuation, which is no word at all
+                '\\' if next_char(&mut units) == Some('\n') => {
+                    units.next();
+                }
+                '\'' | '"' | '\\' => {
+                    let word = stage.word.get_or_insert_default();
+                    Self::unquote(c, &mut units, word, "command")?;
+                }
+                '$' if !shell => match units.env_var() {
+                    // Unquoted empty value is no word at all, like in shell
+                    Some(val) if val.is_empty() => {}
+                    Some(val) => stage.word.get_or_insert_default().push_str(&val),
+                    None => stage.push_char(c),
+                },
+                '(' if shell => {
+                    subshell += 1;
+                    stage.text.push_char(c);
+                }
+                ')' if subshell > 0 => {
+                    subshell -= 1;
+                    stage.text.push_char(c);
+                }
+                _ if subshell > 0 => stage.text.push_char(c),
+                ';' | '&' | '\n' if shell => return Ok(Self::whole(whole)),
+                '|' if shell && next_char(&mut units) == Some('|') => {
+                    return Ok(Self::whole(whole))
+                }
+                ';' | '&' | '(' | ')' => {
+                    return Err(CommandError::Syntax(format!(
+                        "`{c}` is only supported when commands run in a shell"
+                    )))
+                }
+                '|' if next_char(&mut units) == Some('|') => {
+                    return Err(CommandError::Syntax(
+                        "`||` is only supported when commands run in a shell".to_owned(),
+                    ))
+                }
+                '|' => {
+                    let next = std::mem::replace(&mut stage, StageBuilder::new(shell));
+                    stages.push(next.finish()?);
+                }
+                '<' => Self::redirect_in(&mut stage, &mut units)?,
+                '1' | '2' if word_start && next_char(&mut units) == Some('>') => {
+                    units.next();
+                    let fd = Fd::from_digit(c).expect("Checked above");
+                    stage.redirects.push(Self::redirect_out(fd, &mut units)?);
+                }
+                '>' => {
+                    stage.end_word();
+                    let redirect = Self::redirect_out(Fd::Stdout, &mut units)?;
+                    stage.redirects.push(redirect);
+                }
+                c => stage.push_char(c),
+            }
+        }
+        stages.push(stage.finish()?);
+
+        Ok(Self { stages })
+    }
+
+    fn whole(cmd: String) -> Self {
+        Self {
+            stages: vec![Stage {
+                cmd: Cmd::Shell(cmd),
+                redirects: vec![],
+            }],
+        }
+    }
+
+    /// Read a quoted string or an escaped char, starting with `quote`, into
+    /// `word` with the quotes removed. Interpolated values are taken as they
+    /// are, a `\\` before one is dropped.
+    ///
+    /// As in POSIX shells, inside `"..."` a `\\` only escapes `$`, `` ` ``,
+    /// `"`, `\\` and newline, and is kept before anything else: `"%s\n"` is
+    /// `%s\n`. An escaped newline is removed along with its `\\`.
+    fn unquote(
+        quote: char,
+        units: &mut Units<'_>,
+        word: &mut String,
+        what: &str,
+    ) -> CommandResult<()> {
+        let is_char = |u: &Unit| matches!(u, Unit::Char(_));
+        if quote == '\\' {
+            if let Some(Unit::Char(c)) = units.next_if(is_char) {
+                if c != '\n' {
+                    word.push(c);
+                }
+            }
+            return Ok(());
+        }
+        loop {
+            match units.next() {
+                Some(Unit::Char(c)) if c == quote => return Ok(()),
+                Some(Unit::Char('\\')) if quote == '"' => match units.next_if(is_char) {
+                    Some(Unit::Char('\n')) => {}
+                    Some(Unit::Char(c @ ('$' | '`' | '"' | '\\'))) => word.push(c),
+                    Some(Unit::Char(c)) => {
+                        word.push('\\');
+                        word.push(c);
+                    }
+                    // Before a value, or unterminated
+                    _ => {}
+                },
+                Some(Unit::Char('$')) if quote == '"' => {
+                    word.push_str(&units.env_var().unwrap_or_else(|| "$".to_owned()));
+                }
+                Some(Unit::Char(c)) => word.push(c),
+                Some(Unit::Value(val)) => word.push_str(&val.to_string()),
+                None => {
+                    return Err(CommandError::Syntax(format!(
+                        "unterminated {quote} in {what}"
+                    )))
+                }
+            }
+        }
+    }
+
+    /// The rest of `<`, `<<<` or a heredoc, after `<` is consumed.
+    fn redirect_in(stage: &mut StageBuilder, units: &mut Units<'_>) -> CommandResult<()> {
+        if next_char(units) != Some('<') {
+            stage.end_word();
+            let path = Self::target(units, "<")?;
+            stage.redirects.push(Redirect::In(path));
+            return Ok(());
+        }
+        units.next();
+        if next_char(units) == Some('<') {
+            units.next();
+            stage.end_word();
+            let data = Self::target(units, "<<<")?;
+            stage.redirects.push(Redirect::HereString(data));
+        } else if stage.shell {
+            // Heredoc, leave it to the shell
+            stage.text.push_str("<<");
+        } else {
+            return Err(CommandError::Syntax(
+                "`<<` is only supported when commands run in a shell".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The rest of `>`, `>>` or `>&fd`, after `>` is consumed.
+    fn redirect_out(fd: Fd, units: &mut Units<'_>) -> CommandResult<Redirect> {
+        match next_char(units) {
+            Some('>') => {
+                units.next();
+                let path = Self::target(units, ">>")?;
+                Ok(Redirect::Out {
+                    fd,
+                    path,
+                    append: true,
+                })
+            }
+            Some('&') => {
+                units.next();
+                let to = next_char(units)
+                    .and_then(Fd::from_digit)
+                    .ok_or_else(|| CommandError::Syntax("expected `1` or `2` after `>&`".into()))?;
+                units.next();
+                Ok(Redirect::Dup { fd, to })
+            }
+            _ => {
+                let path = Self::target(units, ">")?;
+                Ok(Redirect::Out {
+                    fd,
+                    path,
+                    append: false,
+                })
+            }
+        }
+    }
+
+    /// Read the word after a redirection operator, with quotes removed.
+    /// Interpolated values are taken as they are.
+    fn target(units: &mut Units<'_>, op: &str) -> CommandResult<String> {
+        while units
+            .next_if(|u| matches!(u, Unit::Char(c) if c.is_whitespace()))
+            .is_some()
+        {}
+
+        let mut word = String::new();
+        let mut empty = true;
+        while let Some(unit) = units.next_if(|u| match u {
+            Unit::Char(c) => !c.is_whitespace() && !"|<>;&()".contains(*c),
+            Unit::Value(_) => true,
+        }) {
+            empty = false;
+            match unit {
+                Unit::Value(val) => word.push_str(&val.to_string()),
+                Unit::Char(c @ ('\\' | '\'' | '"')) => {
+                    Self::unquote(c, units, &mut word, &format!("target of `{op}`"))?;
+                }
+                Unit::Char('$') => {
+                    word.push_str(&units.env_var().unwrap_or_else(|| "$".to_owned()));
+                }
+                Unit::Char(c) => word.push(c),
+            }
+        }
+
+        if empty {
+            return Err(CommandError::Syntax(format!(
+                "expected a word after `{op}`"
+            )));
+        }
+        Ok(word)
+    }
+
+    /// Run all stages at once, connected with pipes. The status is the one of
+    /// the last stage, stderr of all stages is collected in order.
+    pub fn run(&self, env: &Env, cwd: &Path) -> CommandResult {
+        let mut children = Vec::with_capacity(self.stages.len());
+        let mut stderrs = Vec::with_capacity(self.stages.len());
+        let mut inputs = vec![];
+        let mut stdout: Option<PipeReader> = None;
+
+        for stage in &self.stages {
+            let stdin = stdout.take().map_or_else(Stdio::null, Stdio::from);
+            let spawned = Self::spawn(stage, stdin, env, cwd);
+            let Spawned {
+                child,
+                stdout: out,
+                stderr,
+                input,
+            } = match spawned {
+                Ok(spawned) => spawned,
+                Err(err) => {
+                    for mut child in children {
+                        Self::kill(&mut child);
+                    }
+                    return Err(err);
+                }
+            };
+            children.push(child);
+            stderrs.push(stderr);
+            inputs.extend(input);
+            stdout = Some(out);
+        }
+
+        // Whatever the last stage wrote to its stdout pipe
+        let stdout = stdout.expect("Pipeline has at least one stage");
+
+        thread::scope(|s| {
+            for (mut child_stdin, data) in inputs {
+                // A command may exit without reading all of its input
+                s.spawn(move || drop(child_stdin.write_all(data.as_bytes())));
+            }
+            let stdout = s.spawn(move || read_all(stdout));
+            let stderrs = stderrs
+                .into_iter()
+                .map(|stderr| s.spawn(move || read_all(stderr)))
+                .collect::<Vec<_>>();
+
+            let mut status = None;
+            for mut child in children {
+                status = Some(child.wait()?);
+            }
+            let status: ExitStatus = status.expect("Pipeline has at least one stage");
+
+            let stdout = stdout.join().expect("Reader panicked")?;
+            let mut stderr = vec![];
+            for handle in stderrs {
+                stderr.extend(handle.join().expect("Reader panicked")?);
+            }
+
+            Ok(Output {
+                status,
+                stdout,
+                stderr,
+            })
+        })
+    }
+
+    fn spawn(stage: &Stage, stdin: Stdio, env: &Env, cwd: &Path) -> CommandResult<Spawned> {
+        let (out_reader, out_writer) = io::pipe()?;
+        let (err_reader, err_writer) = io::pipe()?;
+
+        let mut stdin = stdin;
+        let mut here_string = None;
+        let mut out = Sink::Pipe(out_writer.try_clone()?);
+        let mut err = Sink::Pipe(err_writer.try_clone()?);
+
+        for redirect in &stage.redirects {
+            match redirect {
+                Redirect::Out { fd, path, append } => {
+                    let file = OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .append(*append)
+                        .truncate(!*append)
+                        .open(cwd.join(path))
+                        .map_err(|source| CommandError::Redirect {
+                            path: path.clone(),
+                            source,
+                        })?;
+                    *sink_of(*fd, &mut out, &mut err) = Sink::File(file);
+                }
+                Redirect::Dup { fd, to } => {
+                    let target = match to {
+                        Fd::Stdout => out.try_clone()?,
+                        Fd::Stderr => err.try_clone()?,
+                    };
+                    *sink_of(*fd, &mut out, &mut err) = target;
+                }
+                Redirect::In(path) => {
+                    let file =
+                        File::open(cwd.join(path)).map_err(|source| CommandError::Redirect {
+                            path: path.clone(),
+                            source,
+                        })?;
+                    stdin = file.into();
+                    here_string = None;
+                }
+                Redirect::HereString(data) => {
+                    stdin = Stdio::piped();
+                    here_string = Some(format!("{data}\n"));
+                }
+            }
+        }
+
+        let mut cmd = match &stage.cmd {
+            Cmd::Argv(argv) => {
+                let mut cmd = Command::new(&argv[0]);
+                cmd.args(&argv[1..]);
+                cmd
+            }
+            Cmd::Shell(text) => {
+                let mut cmd = Command::new("sh");
+                cmd.arg("-c").arg(text);
+                cmd
+            }
+        };
+        env.apply(&mut cmd);
+        cmd.current_dir(cwd);
+        cmd.stdin(stdin).stdout(out).stderr(err);
+        let mut child = cmd.spawn().map_err(|source| CommandError::Spawn {
+            program: cmd.get_program().to_string_lossy().into_owned(),
+            source,
+        })?;
+        // Close our copies of the write ends, or readers never see EOF
+        drop(cmd);
+        drop(out_writer);
+        drop(err_writer);
+
+        let input = here_string.map(|data| {
+            let child_stdin = child.stdin.take().expect("Stdin is piped");
+            (child_stdin, data)
+        });
+        Ok(Spawned {
+            child,
+            stdout: out_reader,
+            stderr: err_reader,
+            input,
+        })
+    }
+
+    fn kill(child: &mut Child) {
+        // Already exited is fine, nothing else can be done on failure
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// A running stage, with the read ends of its stdout and stderr, and the
+/// here-string to feed if there is one.
+struct Spawned {
+    child: Child,
+    stdout: PipeReader,
+    stderr: PipeReader,
+    input: Option<(ChildStdin, String)>,
+}
+
+/// Where stdout or stderr of a stage goes.
+enum Sink {
+    Pipe(PipeWriter),
+    File(File),
+}
+
+impl Sink {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Self::Pipe(writer) => Self::Pipe(writer.try_clone()?),
+            Self::File(file) => Self::File(file.try_clone()?),
+        })
+    }
+}
+
+impl From<Sink> for Stdio {
+    fn from(sink: Sink) -> Self {
+        match sink {
+            Sink::Pipe(writer) => writer.into(),
+            Sink::File(file) => file.into(),
+        }
+    }
+}
+
+const fn sink_of<'a>(fd: Fd, out: &'a mut Sink, err: &'a mut Sink) -> &'a mut Sink {
+    match fd {
+        Fd::Stdout => out,
+        Fd::Stderr => err,
+    }
+}
+
+fn empty_command() -> CommandError {
+    CommandError::Syntax("empty command in pipeline".to_owned())
+}
+
+fn next_char(units: &mut Units<'_>) -> Option<char> {
+    match units.iter.peek() {
+        Some(Unit::Char(c)) => Some(*c),
+        _ => None,
+    }
+}
+
+fn read_all(mut reader: PipeReader) -> io::Result<Vec<u8>> {
+    let mut buf = vec![];
+    reader.read_to_end(&mut buf)?;
+    Ok(buf)
+}
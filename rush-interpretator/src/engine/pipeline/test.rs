@@ -0,0 +1,302 @@
+use super::{Cmd, CmdPiece, Fd, Pipeline, Redirect, Stage};
+use crate::{CommandError, CommandResult, Env, Value};
+
+fn env() -> Env {
+    let mut env = Env::new();
+    env.set("HOME", "/home/me");
+    env.set("EMPTY", "");
+    env
+}
+
+fn text(s: &str) -> CmdPiece {
+    CmdPiece::Text(s.to_owned())
+}
+
+fn val(val: impl Into<Value>) -> CmdPiece {
+    CmdPiece::Value(val.into())
+}
+
+fn parse(pieces: Vec<CmdPiece>, shell: bool) -> CommandResult<Pipeline> {
+    Pipeline::parse(pieces, shell, &env())
+}
+
+/// Words of a command of a single stage with no redirections.
+fn words(pieces: Vec<CmdPiece>) -> Vec<String> {
+    match parse(pieces, false).unwrap().stages.as_slice() {
+        [Stage {
+            cmd: Cmd::Argv(words),
+            redirects,
+        }] if redirects.is_empty() => words.clone(),
+        stages => panic!("Expected a single command, got {stages:?}"),
+    }
+}
+
+fn argv(src: &str) -> Vec<String> {
+    words(vec![text(src)])
+}
+
+fn syntax_error(pieces: Vec<CmdPiece>, shell: bool) -> String {
+    match parse(pieces, shell) {
+        Err(CommandError::Syntax(msg)) => msg,
+        other => panic!("Expected a syntax error, got {other:?}"),
+    }
+}
+
+fn shell(text: &str, redirects: Vec<Redirect>) -> Stage {
+    Stage {
+        cmd: Cmd::Shell(text.to_owned()),
+        redirects,
+    }
+}
+
+#[test]
+fn test_words() {
+    assert_eq!(argv(" a  b\tc "), ["a", "b", "c"]);
+    assert_eq!(argv(r#"'a b' "c d"e f'g'"#), ["a b", "c de", "fg"]);
+    assert_eq!(argv("echo '' \"\""), ["echo", "", ""]);
+    assert_eq!(argv(r"a\ b \'c"), ["a b", "'c"]);
+    assert_eq!(argv("a\\\nb c \\\n d"), ["ab", "c", "d"]);
+    // Inside double quotes `\` only escapes `$`, `` ` ``, `"`, `\` and newline
+    assert_eq!(argv(r#"printf "%s\n" a b"#), ["printf", r"%s\n", "a", "b"]);
+    assert_eq!(argv(r#""\$ \` \" \\ \a""#), [r#"$ ` " \ \a"#]);
+    assert_eq!(argv("\"a\\\nb\""), ["ab"]);
+    // Nothing is special inside single quotes
+    assert_eq!(argv(r"'\n \' "), [r"\n \"]);
+    assert_eq!(
+        syntax_error(vec![text("echo \"a")], false),
+        "unterminated \" in command"
+    );
+}
+
+#[test]
+fn test_env_vars() {
+    assert_eq!(
+        argv(r#"$HOME/x "$HOME" '$HOME' \$HOME $ $1"#),
+        ["/home/me/x", "/home/me", "$HOME", "$HOME", "$", "$1"]
+    );
+    // An unset or empty var is no word at all, unless it's quoted
+    assert_eq!(argv(r#"a $EMPTY $UNSET "$EMPTY" b"#), ["a", "", "b"]);
+}
+
+#[test]
+fn test_values() {
+    let xs = Value::from(vec![Value::from("1"), Value::from("2 3")]);
+    assert_eq!(
+        words(vec![
+            text("echo "),
+            val("a b"),
+            text(" x"),
+            val(xs),
+            text("y "),
+            val(Vec::<Value>::new()),
+        ]),
+        ["echo", "a b", "x1", "2 3y"]
+    );
+    // Operators and quotes in values are taken as they are
+    assert_eq!(
+        words(vec![text("echo "), val("a | b; 'c' > d"), text(" e")]),
+        ["echo", "a | b; 'c' > d", "e"]
+    );
+    assert_eq!(
+        words(vec![
+            text(r#"echo "<"#),
+            val("a  b"),
+            text(r#">" \"#),
+            val(1_i64)
+        ]),
+        ["echo", "<a  b>", "1"]
+    );
+}
+
+#[test]
+fn test_redirects() {
+    let pipeline = parse(
+        vec![
+            text("cmd a2>x <in >out 2>> 'err log' b 2>&1 >&2 <<< "),
+            val("some data"),
+        ],
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        pipeline.stages,
+        [Stage {
+            cmd: Cmd::Argv(vec!["cmd".into(), "a2".into(), "b".into()]),
+            redirects: vec![
+                Redirect::Out {
+                    fd: Fd::Stdout,
+                    path: "x".into(),
+                    append: false
+                },
+                Redirect::In("in".into()),
+                Redirect::Out {
+                    fd: Fd::Stdout,
+                    path: "out".into(),
+                    append: false
+                },
+                Redirect::Out {
+                    fd: Fd::Stderr,
+                    path: "err log".into(),
+                    append: true
+                },
+                Redirect::Dup {
+                    fd: Fd::Stderr,
+                    to: Fd::Stdout
+                },
+                Redirect::Dup {
+                    fd: Fd::Stdout,
+                    to: Fd::Stderr
+                },
+                Redirect::HereString("some data".into()),
+            ],
+        }]
+    );
+    // Quoted operators are words
+    assert_eq!(argv("echo '>' \\< \"2>&1\""), ["echo", ">", "<", "2>&1"]);
+    assert_eq!(
+        syntax_error(vec![text("cat < | wc")], false),
+        "expected a word after `<`"
+    );
+    assert_eq!(
+        syntax_error(vec![text("cat >&3")], false),
+        "expected `1` or `2` after `>&`"
+    );
+    assert_eq!(
+        syntax_error(vec![text("cat << EOF")], false),
+        "`<<` is only supported when commands run in a shell"
+    );
+}
+
+#[test]
+fn test_stages() {
+    let pipeline = parse(
+        vec![text("ls -l | grep "), val("a|b"), text("|wc -l")],
+        false,
+    )
+    .unwrap();
+    let cmds = pipeline
+        .stages
+        .into_iter()
+        .map(|stage| stage.cmd)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        cmds,
+        [
+            Cmd::Argv(vec!["ls".into(), "-l".into()]),
+            Cmd::Argv(vec!["grep".into(), "a|b".into()]),
+            Cmd::Argv(vec!["wc".into(), "-l".into()]),
+        ]
+    );
+    assert_eq!(
+        syntax_error(vec![text("ls | | wc")], false),
+        "empty command in pipeline"
+    );
+    for (src, op) in [
+        ("a; b", ";"),
+        ("a && b", "&"),
+        ("(a)", "("),
+        ("a || b", "||"),
+    ] {
+        assert_eq!(
+            syntax_error(vec![text(src)], false),
+            format!("`{op}` is only supported when commands run in a shell")
+        );
+    }
+}
+
+#[test]
+fn test_shell_stages() {
+    assert_eq!(
+        parse(vec![text("ls $HOME | grep 'a|b' 2>&1 | wc > out")], true)
+            .unwrap()
+            .stages,
+        [
+            shell("ls $HOME ", vec![]),
+            shell(
+                " grep 'a|b'  ",
+                vec![Redirect::Dup {
+                    fd: Fd::Stderr,
+                    to: Fd::Stdout
+                }]
+            ),
+            shell(
+                " wc ",
+                vec![Redirect::Out {
+                    fd: Fd::Stdout,
+                    path: "out".into(),
+                    append: false
+                }]
+            ),
+        ]
+    );
+    // Command lists and subshells are left to the shell as a whole
+    for src in [
+        "a; b | c",
+        "a && b | c",
+        "a || b",
+        "echo $(a | b)",
+        "(a | b)",
+    ] {
+        assert_eq!(
+            parse(vec![text(src)], true).unwrap().stages,
+            [shell(src, vec![])]
+        );
+    }
+    // Values are quoted for the shell, and never operators
+    assert_eq!(
+        parse(
+            vec![
+                text("echo "),
+                val("a; b"),
+                text(" \"<"),
+                val("it's"),
+                text(">\"")
+            ],
+            true
+        )
+        .unwrap()
+        .stages,
+        [shell(r#"echo 'a; b' "<"'it'\''s'">""#, vec![])]
+    );
+}
+
+#[test]
+fn test_run() {
+    let dir = std::env::temp_dir().join(format!("rush-pipeline-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let run = |src: &str| {
+        let output = parse(vec![text(src)], false)
+            .unwrap()
+            .run(&env(), &dir)
+            .unwrap();
+        (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+
+    assert_eq!(
+        run("printf 'b\\na\\n' | sort | tr a-z A-Z"),
+        ("A\nB\n".into(), String::new())
+    );
+    assert_eq!(
+        run("tr a-z A-Z <<< 'some data'"),
+        ("SOME DATA\n".into(), String::new())
+    );
+    // stderr of a stage joins its stdout in the pipe, or stays apart
+    assert_eq!(
+        run("sh -c 'echo out; echo err >&2' 2>&1 | sort"),
+        ("err\nout\n".into(), String::new())
+    );
+    assert_eq!(
+        run("sh -c 'echo out; echo err >&2' | tr a-z A-Z"),
+        ("OUT\n".into(), "err\n".into())
+    );
+    // `>` truncates, `>>` appends, `<` reads
+    run("echo a > out");
+    run("echo b > out");
+    run("echo c >> out");
+    assert_eq!(run("cat < out"), ("b\nc\n".into(), String::new()));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
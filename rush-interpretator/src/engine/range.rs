@@ -0,0 +1,30 @@
+use std::fmt;
+
+/// Half-open integer range produced by `start..end` expressions.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Range {
+    pub const fn new(start: i64, end: i64) -> Self {
+        Self { start, end }
+    }
+}
+
+impl IntoIterator for Range {
+    type IntoIter = std::ops::Range<i64>;
+    type Item = i64;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.start..self.end
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
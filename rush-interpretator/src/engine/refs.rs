@@ -20,7 +20,7 @@ impl Ref {
 }
 
 impl Display for Ref {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "Ref({})", self.0)
     }
 }
@@ -2,36 +2,32 @@ use std::{
     collections::{hash_map::DefaultHasher, BTreeMap as Map},
     fmt::Display,
     hash::{Hash, Hasher},
-    ops::Deref,
     sync::atomic::AtomicUsize,
 };
 
 use parser::ast::FnDef;
 
 use crate::{
-    Callable, ExternalFn, FnRef, IntoShared, Ref, RuntimeError, RuntimeResult, Shared, Value,
+    Callable, ExternalFn, FnRef, ModuleId, NativeFn, Ref, RuntimeError, RuntimeResult, Value,
     Variable,
 };
 
 #[must_use]
-pub struct Scope<'a> {
+pub struct Scope {
     name: String,
-    fns: Map<FnRef, Shared<Callable<'a>>>,
     vars: Map<String, Variable>,
 }
 
-impl<'a> Scope<'a> {
+impl Scope {
     pub fn new(name: impl Into<String>) -> Self {
         let name = name.into();
         Self {
             name,
-            fns: Map::new(),
             vars: Map::new(),
         }
     }
 
     pub fn clear(&mut self, name: impl Into<String>) {
-        self.fns.clear();
         self.vars.clear();
         self.name = name.into();
     }
@@ -40,24 +36,39 @@ impl<'a> Scope<'a> {
         Self::new("global")
     }
 
-    pub fn register_script_fn(&mut self, def: FnDef<'a>) {
+    /// New scope with the native functions of `self`, like the builtins of the
+    /// global scope.
+    pub fn natives(&self, name: impl Into<String>) -> Self {
+        let mut scope = Self::new(name);
+        for (name, var) in &self.vars {
+            if let Value::Fn(func) = var.value_ref() {
+                if matches!(**func, Callable::Native(_)) {
+                    scope.new_var(name.clone(), Value::Fn(func.clone()));
+                }
+            }
+        }
+        scope
+    }
+
+    pub fn register_script_fn(&mut self, def: FnDef, home: Option<ModuleId>) {
         let hash = {
             let mut hasher = DefaultHasher::new();
             def.hash(&mut hasher);
             hasher.finish()
         };
-        let fn_ref = Self::new_ref().into();
-        self.new_var(def.ident.name, Value::new(fn_ref));
-        self.fns
-            .insert(fn_ref, Callable::script(def, hash).shared());
+        let name = def.ident.name.clone();
+        self.new_var(name, FnRef::new(Callable::script(def, hash, home)));
     }
 
     pub fn register_external_fn(&mut self, name: impl Into<String>, func: impl ExternalFn) {
         let name = name.into();
-        let fn_ref = FnRef::new(Self::new_ref());
-        let fn_ptr = Callable::native(func, &name).shared();
-        self.new_var(name, Value::new(fn_ref));
-        self.fns.insert(fn_ref, fn_ptr);
+        let func = Callable::native(func, &name);
+        self.new_var(name, FnRef::new(func));
+    }
+
+    pub fn register_native_fn(&mut self, func: NativeFn) {
+        let name = func.name().to_owned();
+        self.new_var(name, FnRef::new(Callable::Native(func)));
     }
 
     pub fn register_boxed_external_fn(
@@ -66,10 +77,8 @@ impl<'a> Scope<'a> {
         func: Box<dyn ExternalFn>,
     ) {
         let name = name.into();
-        let fn_ref = FnRef::new(Self::new_ref());
-        let fn_ptr = Callable::native_boxed(func, &name).shared();
-        self.new_var(name, Value::new(fn_ref));
-        self.fns.insert(fn_ref, fn_ptr);
+        let func = Callable::native_boxed(func, &name);
+        self.new_var(name, FnRef::new(func));
     }
 
     pub fn new_var(&mut self, name: impl Into<String>, val: impl Into<Value>) -> Ref {
@@ -79,13 +88,6 @@ impl<'a> Scope<'a> {
         ret
     }
 
-    pub fn get_fn(&self, fn_ref: FnRef) -> RuntimeResult<Shared<Callable<'a>>> {
-        self.fns
-            .get(&fn_ref)
-            .cloned()
-            .ok_or_else(|| RuntimeError::NullRefError(*fn_ref.deref()))
-    }
-
     pub fn search(&self, val_ref: &Ref) -> RuntimeResult<&Variable> {
         self.vars
             .values()
@@ -99,6 +101,10 @@ impl<'a> Scope<'a> {
             .ok_or_else(|| RuntimeError::IdentNotFound(name.to_string()))
     }
 
+    pub fn vars(&self) -> impl Iterator<Item = (&str, &Variable)> {
+        self.vars.iter().map(|(name, var)| (name.as_str(), var))
+    }
+
     pub fn get_mut(&mut self, name: &str) -> RuntimeResult<&mut Variable> {
         self.vars
             .get_mut(name)
@@ -111,8 +117,8 @@ impl<'a> Scope<'a> {
     }
 }
 
-impl Display for Scope<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "Scope({})", self.name)
     }
 }
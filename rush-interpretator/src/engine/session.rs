@@ -0,0 +1,54 @@
+use crate::{Context, Engine, FnCallArg, Result, RuntimeError, Value};
+
+/// Context kept across any number of [`eval`](Session::eval)s, so variables
+/// and functions defined by a chunk of script are there for the next ones.
+#[must_use]
+pub struct Session {
+    ctx: Context,
+}
+
+impl Session {
+    pub fn new(engine: Engine) -> Result<Self> {
+        Ok(Self {
+            ctx: engine.into_context()?,
+        })
+    }
+
+    /// Run a chunk of script, returning the value of its trailing expression.
+    pub fn eval(&mut self, src: &str) -> Result<Value> {
+        let tree = self.ctx.load("<eval>", src)?;
+        self.ctx.run(&tree)
+    }
+
+    /// Value of a global variable or function.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.ctx.get_global(name)
+    }
+
+    /// Define or overwrite a global variable.
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<Value>) {
+        self.ctx.set_global(name, value);
+    }
+
+    /// Call a global function.
+    pub fn call(&mut self, name: &str, args: FnCallArg) -> Result<Value> {
+        let func = self
+            .ctx
+            .get_global(name)
+            .ok_or_else(|| RuntimeError::IdentNotFound(name.to_owned()))?;
+        self.ctx.call_value(&func, args)
+    }
+
+    pub const fn context(&mut self) -> &mut Context {
+        &mut self.ctx
+    }
+}
+
+impl Engine {
+    /// Start a [`Session`] with the functions, config, env and cwd of the
+    /// engine.
+    pub fn session(self) -> Result<Session> {
+        Session::new(self)
+    }
+}
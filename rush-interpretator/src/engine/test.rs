@@ -0,0 +1,959 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use crate::{
+    shell_quote, shell_words, CommandError, Engine, Env, Error, MemoryLoader, Overflow,
+    RuntimeError, Session, TraceEvent, Value,
+};
+
+fn session() -> Session {
+    Engine::new().with_builtins().session().unwrap()
+}
+
+fn eval(src: &str) -> Value {
+    session().eval(src).unwrap()
+}
+
+fn runtime_error(res: crate::Result<Value>) -> RuntimeError {
+    match res {
+        Err(Error::Runtime { error, .. }) => *error,
+        other => panic!("Expected a runtime error, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_return() {
+    assert_eq!(eval("fn f(x) { x + 1 } f(1)"), Value::Int(2));
+    assert_eq!(
+        eval("fn f(x) { if x > 0 { return 1; } 0 } f(5) * 10 + f(0)"),
+        Value::Int(10)
+    );
+    // `return` leaves every loop and block of the function
+    assert_eq!(
+        eval("fn f() { for i in 0..10 { while true { { return i + 7; } } } } f()"),
+        Value::Int(7)
+    );
+    assert_eq!(eval("fn f() { return; } f()"), Value::Unit);
+    // `return` at top level ends the script
+    assert_eq!(eval("let x = 1; return x + 1; x = 3; x"), Value::Int(2));
+    // Only the whole word is a keyword
+    assert_eq!(
+        eval("fn return_value(x) { return(x); } let returned = return_value(2); returned"),
+        Value::Int(2)
+    );
+}
+
+#[test]
+fn test_block_value() {
+    assert_eq!(eval("{ let x = 2; x * 3 }"), Value::Int(6));
+    assert_eq!(eval("{ 1; }"), Value::Unit);
+    // A block ending with an `if` takes its value
+    assert_eq!(
+        eval("fn sign(x) { if x < 0 { 0 - 1 } else { if x == 0 { 0 } else { 1 } } } sign(0 - 5)"),
+        Value::Int(-1)
+    );
+    assert_eq!(eval("fn f() { if false { 1 } } f()"), Value::Unit);
+    // Variables of a block are gone after it
+    assert!(matches!(
+        runtime_error(session().eval("if true { let y = 1; } y")),
+        RuntimeError::IdentNotFound(name) if name == "y"
+    ));
+}
+
+#[test]
+fn test_break_continue() {
+    assert_eq!(
+        eval(
+            "let n = 0; for i in 0..10 { if i % 2 == 0 { continue; } if i > 6 { break; } n = n + \
+             i; } n"
+        ),
+        Value::Int(1 + 3 + 5)
+    );
+    // `break value` is the value of the loop, and of a block ending with it
+    assert_eq!(
+        eval(
+            "fn root() { let i = 0; while true { i = i + 1; if i * i > 50 { break i; } } } root()"
+        ),
+        Value::Int(8)
+    );
+    // Only the innermost loop is left
+    assert_eq!(
+        eval("let n = 0; for i in 0..3 { for j in 0..10 { if j == 2 { break; } n = n + 1; } } n"),
+        Value::Int(6)
+    );
+    // Names starting with a keyword are no loop control
+    assert_eq!(
+        eval(
+            "let breaker = 0; let continued = 0; for i in 0..3 { breaker = breaker + 1; continued \
+             = breaker; } continued"
+        ),
+        Value::Int(3)
+    );
+}
+
+#[test]
+fn test_stray_control_flow() {
+    let mut session = session();
+    let err = session.eval("let x = 1;\ncontinue;").unwrap_err();
+    assert_eq!(err.to_string(), "`continue` outside of a loop");
+    let span = err.span().unwrap();
+    assert_eq!(
+        &session.context().sources().get(span.file).unwrap().src[span.start..span.end],
+        "continue;"
+    );
+    // Loop control doesn't go through function calls
+    let err = session
+        .eval("fn f() { break; } for i in 0..3 { f(); }")
+        .unwrap_err();
+    assert!(matches!(
+        runtime_error(Err(err)),
+        RuntimeError::BreakOutsideLoop
+    ));
+}
+
+#[test]
+fn test_scopes_after_error() {
+    let mut session = session();
+    let err =
+        session.eval("fn f(n) { let local = n; if n == 0 { 1 + true } else { f(n - 1) } } f(5)");
+    assert!(matches!(runtime_error(err), RuntimeError::TypeError { .. }));
+    // Every scope the error went through has been left
+    drop(session.eval("let x = 1;").unwrap());
+    assert_eq!(session.get("x"), Some(Value::Int(1)));
+    assert!(session.eval("local").is_err());
+}
+
+#[test]
+fn test_list() {
+    assert_eq!(eval("let xs = [1, 2, 3,]; xs[0] + xs[2]"), Value::Int(4));
+    assert_eq!(
+        eval("let xs = [[1, 2], [3]]; xs[0][1] = 5; push(xs[1], 4); xs"),
+        Value::from(vec![
+            Value::from(vec![1_i64, 5]),
+            Value::from(vec![3_i64, 4]),
+        ])
+    );
+    // Copies of a list share its items
+    assert_eq!(
+        eval("let xs = []; let ys = xs; push(ys, 1); len(xs)"),
+        Value::Int(1)
+    );
+    assert_eq!(
+        eval("let n = 0; for x in [1, 2, 3] { n = n * 10 + x; } n"),
+        Value::Int(123)
+    );
+    assert!(matches!(
+        runtime_error(session().eval("[1, 2][2]")),
+        RuntimeError::IndexOutOfBounds { index: 2, len: 2 }
+    ));
+    assert!(matches!(
+        runtime_error(session().eval("[1][0 - 1]")),
+        RuntimeError::IndexOutOfBounds { index: -1, .. }
+    ));
+    // A list can contain itself, it's printed once and equal to itself only
+    let mut session = session();
+    drop(
+        session
+            .eval("let xs = [1]; push(xs, xs); let ys = [1]; push(ys, ys);")
+            .unwrap(),
+    );
+    assert_eq!(
+        session.eval("[str(xs), str([xs])]").unwrap(),
+        Value::from(vec!["[1, [...]]", "[[1, [...]]]"])
+    );
+    assert_eq!(session.eval("xs == xs[1]").unwrap(), Value::Bool(true));
+    assert_eq!(session.eval("[xs] == [xs]").unwrap(), Value::Bool(true));
+    assert_eq!(session.eval("xs == ys").unwrap(), Value::Bool(false));
+    assert_eq!(session.eval("xs == [1, 2]").unwrap(), Value::Bool(false));
+}
+
+#[test]
+fn test_map() {
+    assert_eq!(
+        eval(r#"let m = #{ a: 1, "b c": 2 }; m.a + m["b c"]"#),
+        Value::Int(3)
+    );
+    assert_eq!(
+        eval(r#"let m = #{ a: #{ b: 1 } }; m.a.b = 2; m["c"] = 3; m"#),
+        eval("#{ a: #{ b: 2 }, c: 3 }")
+    );
+    assert_eq!(eval("keys(#{ b: 1, a: 2 })"), eval(r#"["a", "b"]"#));
+    // Entries are iterated as `[key, value]`, in key order
+    assert_eq!(
+        eval(
+            r#"let s = ""; for entry in #{ y: 2, x: 1 } { s = "${s}${entry[0]}=${entry[1]} "; } s"#
+        ),
+        Value::from("x=1 y=2 ")
+    );
+    assert!(matches!(
+        runtime_error(session().eval("#{ a: 1 }.b")),
+        RuntimeError::KeyNotFound(key) if key == "b"
+    ));
+    // A map can contain itself, directly or through a list
+    let mut session = session();
+    drop(
+        session
+            .eval("let m = #{ a: 1 }; m.me = m; let n = #{ xs: [] }; push(n.xs, n);")
+            .unwrap(),
+    );
+    assert_eq!(
+        session.eval("[str(m), str(n)]").unwrap(),
+        Value::from(vec!["#{a: 1, me: #{...}}", "#{xs: [#{...}]}"])
+    );
+    assert_eq!(session.eval("m == m.me").unwrap(), Value::Bool(true));
+    assert_eq!(session.eval("n.xs == [n]").unwrap(), Value::Bool(true));
+    assert_eq!(session.eval("m == n").unwrap(), Value::Bool(false));
+    assert_eq!(
+        session.eval("n == #{ xs: [#{ xs: [] }] }").unwrap(),
+        Value::Bool(false)
+    );
+}
+
+#[test]
+fn test_collection_args() {
+    let mut session = Engine::new()
+        .with_fn("sum", |xs: Vec<i64>| Ok(Value::Int(xs.iter().sum())))
+        .with_fn("total", |m: std::collections::BTreeMap<String, i64>| {
+            Ok(Value::Int(m.values().sum()))
+        })
+        .session()
+        .unwrap();
+    assert_eq!(session.eval("sum([1, 2, 3])").unwrap(), Value::Int(6));
+    assert_eq!(
+        session.eval("total(#{ a: 1, b: 2 })").unwrap(),
+        Value::Int(3)
+    );
+    assert_eq!(
+        runtime_error(session.eval(r#"sum([1, "2"])"#)).to_string(),
+        "Type of `ExternalFn(sum) Arg#0[1]` mismatched: expect `int`, found `str`"
+    );
+}
+
+#[test]
+fn test_closure() {
+    assert_eq!(eval("let add = |a, b| a + b; add(1, 2)"), Value::Int(3));
+    assert_eq!(
+        eval("let f = fn(x) { let y = x * 2; y + 1 }; f(3)"),
+        Value::Int(7)
+    );
+    // Locals are captured by value, lists and maps are shared
+    assert_eq!(
+        eval(
+            "fn f() { let n = 1; let xs = []; let g = |x| { push(xs, x); x + n }; n = 10; [g(1), \
+             len(xs)] } f()"
+        ),
+        eval("[2, 1]")
+    );
+    // Each closure created in a loop captures its own variable
+    assert_eq!(
+        eval("let fs = []; for i in 0..3 { push(fs, || i * 10); } let f = fs[2]; f()"),
+        Value::Int(20)
+    );
+    assert_eq!(eval("let f = |x| x; let g = f; f == g"), Value::Bool(true));
+    assert_eq!(
+        eval("let f = |x| x; let g = |x| x; f == g"),
+        Value::Bool(false)
+    );
+}
+
+#[test]
+fn test_fn_args() {
+    assert_eq!(eval("map([1, 2, 3], |x| x * 2)"), eval("[2, 4, 6]"));
+    assert_eq!(
+        eval("fn even(x) { x % 2 == 0 } filter([1, 2, 3, 4], even)"),
+        eval("[2, 4]")
+    );
+    assert_eq!(
+        eval("let n = 0; each([1, 2, 3], |x| { n = n + x; }); n"),
+        Value::Int(6)
+    );
+    // Natives calling back into closures that call natives
+    assert_eq!(
+        eval("map([[1], [2, 3]], |xs| len(map(xs, |x| x)))"),
+        eval("[1, 2]")
+    );
+}
+
+#[test]
+fn test_shell_quote() {
+    assert_eq!(shell_quote("plain-word_1.txt"), "plain-word_1.txt");
+    assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    assert_eq!(shell_quote(""), "''");
+    assert_eq!(shell_words(&eval(r#"["a b", 1]"#)), "'a b' 1");
+    // What the shell reads back is the word itself, whatever it contains
+    for word in [
+        "it's",
+        "a; echo PWNED",
+        "$(echo PWNED)",
+        "`echo PWNED`",
+        "line\nbreak",
+        "$HOME ~ * ?",
+        "X=1",
+        r"back\slash",
+        "'\"'",
+        " ",
+        "",
+    ] {
+        let out = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("printf %s {}", shell_quote(word)))
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8(out.stdout).unwrap(), word);
+    }
+}
+
+#[test]
+fn test_shell_mode_values() {
+    let mut session = Engine::new().use_shell(true).session().unwrap();
+    session.set("x", "a; echo PWNED");
+    // Values are spliced as they are, inside quotes of either kind or not
+    for (src, out) in [
+        ("$`echo ${x}`", "a; echo PWNED\n"),
+        ("$`echo '${x}'`", "a; echo PWNED\n"),
+        (r#"$`echo "v=${x}"`"#, "v=a; echo PWNED\n"),
+        (
+            r#"$`echo "'${x}'" '"${x}"'`"#,
+            "'a; echo PWNED' \"a; echo PWNED\"\n",
+        ),
+        (
+            r#"$`echo \'${x}; echo "\"${x}"`"#,
+            "'a; echo PWNED\n\"a; echo PWNED\n",
+        ),
+    ] {
+        assert_eq!(session.eval(src).unwrap(), Value::from(out), "{src}");
+    }
+    // A value is part of the quoted word around it
+    session.set("y", "b  c");
+    assert_eq!(
+        session
+            .eval(r#"$`printf '<%s>' "a${y}d" a'${y}'d ${y}`"#)
+            .unwrap(),
+        Value::from("<ab  cd><ab  cd><b  c>")
+    );
+}
+
+#[test]
+fn test_exec_result() {
+    let result = |status: Value, stdout: &str, stderr: &str, success: bool| {
+        Value::from(std::collections::BTreeMap::from([
+            ("status".to_owned(), status),
+            ("stdout".to_owned(), stdout.into()),
+            ("stderr".to_owned(), stderr.into()),
+            ("success".to_owned(), success.into()),
+        ]))
+    };
+    assert_eq!(
+        eval("$?`echo out`"),
+        result(Value::Int(0), "out\n", "", true)
+    );
+    assert_eq!(
+        eval("$?`sh -c 'echo out; echo err >&2; exit 3'`"),
+        result(Value::Int(3), "out\n", "err\n", false)
+    );
+    // No exit status when a signal killed the command
+    assert_eq!(
+        eval("$?`sh -c 'kill -9 $$'`"),
+        result(Value::Unit, "", "", false)
+    );
+    assert_eq!(
+        eval("let res = $?`sh -c 'exit 2'`; [res.success, res.status]"),
+        Value::from(vec![Value::Bool(false), Value::Int(2)])
+    );
+}
+
+#[test]
+fn test_fail_on_nonzero() {
+    // The output of a failed command is taken as it is by default
+    assert_eq!(eval("$`sh -c 'echo out; exit 3'`"), Value::from("out\n"));
+
+    let mut session = Engine::new().fail_on_nonzero(true).session().unwrap();
+    let err = session.eval("$`sh -c 'echo out; exit 3'`").unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Command {
+            error: CommandError::NonZeroExit { ref cmd, code: Some(3) },
+            ..
+        } if cmd == "sh -c 'echo out; exit 3'"
+    ));
+    assert_eq!(
+        err.to_string(),
+        "Command `sh -c 'echo out; exit 3'` failed with exit code 3"
+    );
+    assert_eq!(
+        session
+            .eval("$`sh -c 'kill -9 $$'`")
+            .unwrap_err()
+            .to_string(),
+        "Command `sh -c 'kill -9 $$'` failed with no exit code"
+    );
+    // Values are part of the reported command
+    session.set("script", "exit 4");
+    assert_eq!(
+        session.eval("$`sh -c ${script}`").unwrap_err().to_string(),
+        "Command `sh -c 'exit 4'` failed with exit code 4"
+    );
+    // Successful commands, and `$?` whatever the status, don't fail
+    assert_eq!(session.eval("$`echo ok`").unwrap(), Value::from("ok\n"));
+    assert_eq!(session.eval("$?`false`.status").unwrap(), Value::Int(1));
+}
+
+#[test]
+fn test_with_env() {
+    let mut session = Engine::new()
+        .with_env(Env::new())
+        .with_env_var("A", "a")
+        .with_env_var("B", "b")
+        .with_builtins()
+        .session()
+        .unwrap();
+    let out = session
+        .eval(
+            "let out = []; with_env #{ A: 1, B: (), C: \"c\" } { push(out, [$A, $B, $C]); $A = \
+             \"x\"; $B = \"y\"; $D = \"d\"; } out",
+        )
+        .unwrap();
+    assert_eq!(out, eval(r#"[["1", (), "c"]]"#));
+    // The vars of `with_env` are restored, others set in its block are kept
+    assert_eq!(
+        session.eval("[$A, $B, $C, $D]").unwrap(),
+        eval(r#"["a", "b", (), "d"]"#)
+    );
+    // Even when the block fails
+    assert!(session
+        .eval(r#"with_env #{ A: 2 } { $E = "e"; 1 + true; }"#)
+        .is_err());
+    assert_eq!(session.eval("[$A, $E]").unwrap(), eval(r#"["a", "e"]"#));
+}
+
+#[test]
+fn test_trace() {
+    let traces = Rc::new(RefCell::new(vec![]));
+    let sink = traces.clone();
+    let mut session = Engine::new()
+        .with_module_loader(MemoryLoader::new().with_module("m", "fn f() {\n  $`echo m`\n}"))
+        .with_trace(move |event: &TraceEvent| {
+            sink.borrow_mut().push((
+                event.cmd.to_owned(),
+                event.file.to_owned(),
+                event.line,
+                event.col,
+            ));
+        })
+        .session()
+        .unwrap();
+    drop(
+        session
+            .eval("let x = \"a b\";\nlet out = $`echo ${x}`;\nuse m; m::f();")
+            .unwrap(),
+    );
+    assert_eq!(session.get("out"), Some(Value::from("a b\n")));
+    // Commands are traced after interpolation, where they are
+    assert_eq!(
+        *traces.borrow(),
+        [
+            ("echo 'a b'".to_owned(), "<eval>".to_owned(), 2, 11),
+            ("echo m".to_owned(), "m.rush".to_owned(), 2, 3),
+        ]
+    );
+    // A command is traced even if it fails to run
+    assert!(session.eval("$`no-such-command-rush`").is_err());
+    assert_eq!(traces.borrow().len(), 3);
+
+    let event = TraceEvent {
+        time: UNIX_EPOCH + Duration::from_millis(1_500),
+        cmd: "ls -l",
+        file: "a.rush",
+        line: 3,
+        col: 5,
+    };
+    assert_eq!(event.to_string(), "+ [1.500] a.rush:3:5 ls -l");
+}
+
+#[test]
+fn test_cwd() {
+    let dir = std::env::temp_dir().join(format!("rush-cwd-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("a/b")).unwrap();
+    std::fs::write(dir.join("a/file"), "").unwrap();
+    let dir = dir.canonicalize().unwrap();
+    let host_cwd = std::env::current_dir().unwrap();
+    let mut session = Engine::new()
+        .with_builtins()
+        .with_cwd(&dir)
+        .session()
+        .unwrap();
+    let path = |sub: &str| Value::from(dir.join(sub).display().to_string());
+
+    drop(session.eval(r#"cd("a");"#).unwrap());
+    assert_eq!(session.eval("pwd()").unwrap(), path("a"));
+    // Commands run in the directory of the script, the host's isn't changed
+    assert_eq!(session.eval("$`ls`").unwrap(), Value::from("b\nfile\n"));
+    assert_eq!(std::env::current_dir().unwrap(), host_cwd);
+    // `within` changes back however its block exits
+    assert_eq!(
+        session
+            .eval(r#"let inner = (); within "b" { inner = pwd(); } [inner, pwd()]"#)
+            .unwrap(),
+        Value::from(vec![path("a/b"), path("a")])
+    );
+    assert!(session
+        .eval(r#"within "b" { cd(".."); 1 + true; }"#)
+        .is_err());
+    assert_eq!(session.eval("pwd()").unwrap(), path("a"));
+    drop(session.eval(r#"cd("b/.."); cd("..");"#).unwrap());
+    assert_eq!(
+        session.eval("pwd()").unwrap(),
+        Value::from(dir.display().to_string())
+    );
+    session.context().set_cwd("a/b").unwrap();
+    assert_eq!(
+        session.eval("$`pwd`").unwrap(),
+        Value::from(format!("{}\n", dir.join("a/b").display()))
+    );
+    for bad in ["../file", "missing"] {
+        assert!(matches!(
+            runtime_error(session.eval(&format!("cd({bad:?})"))),
+            RuntimeError::ChangeDir { path, .. } if path == bad
+        ));
+    }
+    assert_eq!(session.eval("pwd()").unwrap(), path("a/b"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_session() {
+    let mut session = session();
+    // Variables and functions of a chunk are there for the next ones
+    drop(session.eval("let n = 1; fn add(a, b) { a + b }").unwrap());
+    assert_eq!(session.eval("n = add(n, 2); n").unwrap(), Value::Int(3));
+    assert_eq!(session.get("n"), Some(Value::Int(3)));
+    assert!(session.get("missing").is_none());
+
+    session.set("n", 10_i64);
+    session.set("name", "rush");
+    assert_eq!(
+        session.eval(r#""${ name }: ${add(n, 1)}""#).unwrap(),
+        Value::from("rush: 11")
+    );
+    assert_eq!(
+        session
+            .call("add", vec![Value::Int(2), Value::Int(5)])
+            .unwrap(),
+        Value::Int(7)
+    );
+    drop(session.eval("let twice = |x| x * 2;").unwrap());
+    assert_eq!(
+        session.call("twice", vec![Value::Int(4)]).unwrap(),
+        Value::Int(8)
+    );
+    assert_eq!(
+        session.call("len", vec![eval("[1, 2]")]).unwrap(),
+        Value::Int(2)
+    );
+    assert!(matches!(
+        runtime_error(session.call("missing", vec![])),
+        RuntimeError::IdentNotFound(name) if name == "missing"
+    ));
+    assert!(matches!(
+        runtime_error(session.call("n", vec![])),
+        RuntimeError::TypeError { .. }
+    ));
+    // A chunk that fails to parse doesn't run at all, one that fails to run
+    // keeps what it did before the error
+    assert!(session.eval("n = 20; let = ;").is_err());
+    assert_eq!(session.get("n"), Some(Value::Int(10)));
+    assert!(session.eval("n = 30; 1 + true").is_err());
+    assert_eq!(session.get("n"), Some(Value::Int(30)));
+}
+
+#[test]
+fn test_diagnostic() {
+    let mut session = Engine::new()
+        .with_module_loader(MemoryLoader::new().with_module("m", "fn f(x) {\n  x + true\n}"))
+        .session()
+        .unwrap();
+    // Runtime errors point at the innermost expression that failed, in the file
+    // it's in
+    let err = session.eval("use m;\nlet a = 1;\nm::f(a)").unwrap_err();
+    assert_eq!(
+        err.diagnostic(session.context().sources()).to_string(),
+        [
+            "error: Type of `<right of (+)>` mismatched: expect `int or float`, found `bool`",
+            " --> m.rush:2:3",
+            "  |",
+            "2 |   x + true",
+            "  |   ^^^^^^^^",
+        ]
+        .join("\n")
+    );
+    let err = session.eval("let b = 2;\nlet c = b + d;").unwrap_err();
+    assert_eq!(
+        err.diagnostic(session.context().sources()).to_string(),
+        [
+            "error: Identifier `d` not found",
+            " --> <eval>:2:13",
+            "  |",
+            "2 | let c = b + d;",
+            "  |             ^",
+        ]
+        .join("\n")
+    );
+    // Commands that fail are located too
+    let err = Engine::new()
+        .fail_on_nonzero(true)
+        .session()
+        .unwrap()
+        .eval("let x = 1;\n  $`false`")
+        .unwrap_err();
+    let span = err.span().unwrap();
+    assert_eq!((span.start, span.end), (13, 21));
+    // One diagnostic per syntax error
+    let err = session.eval("let x = 1 +;\nlet y = ;").unwrap_err();
+    let diagnostics = err.diagnostics(session.context().sources());
+    assert_eq!(diagnostics.len(), 2);
+    assert!(diagnostics[1].to_string().contains("<eval>:2:"));
+}
+
+#[test]
+fn test_traceback() {
+    let mut session = session();
+    let traceback = |session: &mut Session, src: &str| {
+        let err = session.eval(src).unwrap_err();
+        err.traceback(session.context().sources()).to_string()
+    };
+    // No function call, no traceback
+    assert_eq!(traceback(&mut session, "1 + true"), "");
+    assert_eq!(
+        traceback(
+            &mut session,
+            "fn f(x) {\n  let y = x + \"s\";\n  y\n}\nlet a = 1;\nprint(f(a));"
+        ),
+        [
+            "Traceback (most recent call last):",
+            "  File \"<eval>\", line 6, in <script>",
+            "    print(f(a));",
+            "  File \"<eval>\", line 2, in f",
+            "    let y = x + \"s\";",
+            "",
+        ]
+        .join("\n")
+    );
+    // Native frames have no source, closures have no name
+    assert_eq!(
+        traceback(&mut session, "let g = |x| x + true;\nmap([1], g)"),
+        [
+            "Traceback (most recent call last):",
+            "  File \"<eval>\", line 2, in <script>",
+            "    map([1], g)",
+            "  In native function `map`",
+            "  File \"<eval>\", line 1, in <closure>",
+            "    let g = |x| x + true;",
+            "",
+        ]
+        .join("\n")
+    );
+    // Only the first 3 of the same entries in a row are shown
+    assert_eq!(
+        traceback(
+            &mut session,
+            "fn r(n) {\n  if n == 0 {\n    1 + true\n  } else {\n    r(n - 1)\n  }\n}\nr(5)"
+        ),
+        [
+            "Traceback (most recent call last):",
+            "  File \"<eval>\", line 8, in <script>",
+            "    r(5)",
+            "  File \"<eval>\", line 5, in r",
+            "    r(n - 1)",
+            "  File \"<eval>\", line 5, in r",
+            "    r(n - 1)",
+            "  File \"<eval>\", line 5, in r",
+            "    r(n - 1)",
+            "  [Previous line repeated 2 more times]",
+            "  File \"<eval>\", line 3, in r",
+            "    1 + true",
+            "",
+        ]
+        .join("\n")
+    );
+}
+
+#[test]
+fn test_recursion() {
+    let mut session = session();
+    drop(
+        session
+            .eval("fn depth(n) { if n == 0 { 0 } else { depth(n - 1) + 1 } }")
+            .unwrap(),
+    );
+    assert_eq!(session.eval("depth(1000)").unwrap(), Value::Int(1000));
+    // Runaway recursion is an error, not a crash, even on the small stack of a
+    // test thread
+    assert!(matches!(
+        runtime_error(session.eval("depth(100000)")),
+        RuntimeError::MaxRecursionExceeded
+    ));
+    // Also through natives calling back into the script, where the frames are
+    // named after the function
+    let err = session.eval("fn g(x) { map([x], g) } g(1)").unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Runtime { ref error, .. } if matches!(**error, RuntimeError::MaxRecursionExceeded)
+    ));
+    assert!(err.trace()[..4]
+        .iter()
+        .map(|frame| frame.name.as_str())
+        .eq(["map", "g", "map", "g"]));
+    assert_eq!(session.eval("depth(10)").unwrap(), Value::Int(10));
+}
+
+#[test]
+fn test_arith() {
+    // `/` and `%` round towards negative infinity, `/` doesn't truncate
+    for (src, res) in [
+        ("7 / 2", 3),
+        ("-7 / 2", -4),
+        ("7 / -2", -4),
+        ("-7 / -2", 3),
+        ("-6 / 2", -3),
+        ("7 % 3", 1),
+        ("-7 % 3", 2),
+        ("7 % -3", -2),
+        ("-7 % -3", -1),
+        ("-6 % 3", 0),
+        ("2 ** 10", 1024),
+        ("-2 ** 3", -8),
+        ("(-2) ** 2", 4),
+        ("-3 ** 0", 1),
+        ("2 ** 3 ** 2", 512),
+        ("-1 ** 9223372036854775807", -1),
+    ] {
+        assert_eq!(eval(src), Value::Int(res), "{src}");
+    }
+    for src in ["1 / 0", "1 % 0", "-1 / 0"] {
+        assert!(
+            matches!(
+                runtime_error(session().eval(src)),
+                RuntimeError::DivisionByZero
+            ),
+            "{src}"
+        );
+    }
+    assert!(matches!(
+        runtime_error(session().eval("2 ** -1")),
+        RuntimeError::NegativeExponent(-1)
+    ));
+}
+
+#[test]
+fn test_overflow() {
+    let eval_with = |overflow: Overflow, src: &str| {
+        Engine::new()
+            .with_overflow(overflow)
+            .session()
+            .unwrap()
+            .eval(src)
+    };
+    let max = "9223372036854775807";
+    let min = "(-9223372036854775807 - 1)";
+    let cases = [
+        format!("{max} + 1"),
+        format!("{min} - 1"),
+        format!("{max} * 2"),
+        format!("{min} / -1"),
+        format!("-{min}"),
+        "2 ** 64".to_owned(),
+        "(-2) ** 63 * 2".to_owned(),
+    ];
+
+    for src in &cases {
+        assert!(
+            matches!(
+                runtime_error(eval_with(Overflow::Checked, src)),
+                RuntimeError::Overflow { .. }
+            ),
+            "{src}"
+        );
+    }
+    let wrapped = [i64::MIN, i64::MAX, -2, i64::MIN, i64::MIN, 0, 0];
+    for (src, res) in cases.iter().zip(wrapped) {
+        assert_eq!(
+            eval_with(Overflow::Wrapping, src).unwrap(),
+            Value::Int(res),
+            "{src}"
+        );
+    }
+    let saturated = [
+        i64::MAX,
+        i64::MIN,
+        i64::MAX,
+        i64::MAX,
+        i64::MAX,
+        i64::MAX,
+        i64::MIN,
+    ];
+    for (src, res) in cases.iter().zip(saturated) {
+        assert_eq!(
+            eval_with(Overflow::Saturating, src).unwrap(),
+            Value::Int(res),
+            "{src}"
+        );
+    }
+    // Exponents beyond `u32::MAX` are used whole
+    for (overflow, src, res) in [
+        (
+            Overflow::Wrapping,
+            "3 ** 4294967296",
+            2_491_309_678_558_969_857,
+        ),
+        (
+            Overflow::Wrapping,
+            "3 ** 4294967295",
+            830_436_559_519_656_619,
+        ),
+        (
+            Overflow::Wrapping,
+            &format!("3 ** {max}"),
+            -6_148_914_691_236_517_205,
+        ),
+        (Overflow::Wrapping, "(-3) ** 4611686018427387905", -3),
+        (Overflow::Wrapping, &format!("2 ** {max}"), 0),
+        (Overflow::Checked, "(-1) ** 4294967296", 1),
+        (Overflow::Checked, &format!("(-1) ** {max}"), -1),
+        (Overflow::Checked, &format!("0 ** {max}"), 0),
+        (Overflow::Saturating, "(-3) ** 4294967296", i64::MAX),
+        (Overflow::Saturating, "(-3) ** 4294967297", i64::MIN),
+    ] {
+        assert_eq!(eval_with(overflow, src).unwrap(), Value::Int(res), "{src}");
+    }
+    assert!(matches!(
+        runtime_error(eval_with(Overflow::Checked, "3 ** 4294967296")),
+        RuntimeError::Overflow { .. }
+    ));
+    // The remainder always fits, and division by zero fails in every mode
+    for overflow in [Overflow::Checked, Overflow::Wrapping, Overflow::Saturating] {
+        assert_eq!(
+            eval_with(overflow, &format!("{min} % -1")).unwrap(),
+            Value::Int(0)
+        );
+        assert!(matches!(
+            runtime_error(eval_with(overflow, "1 / 0")),
+            RuntimeError::DivisionByZero
+        ));
+    }
+}
+
+#[test]
+fn test_mixed_numbers() {
+    // An int meeting a float becomes a float, on either side
+    for (src, res) in [
+        ("1 + 0.5", 1.5),
+        ("0.5 + 1", 1.5),
+        ("3 - 0.5", 2.5),
+        ("2 * 1.5", 3.0),
+        ("7 / 2.0", 3.5),
+        ("-7 % 2.0", 1.0),
+        ("16 ** 0.5 ** 2", 2.0),
+        ("-(1 + 0.5)", -1.5),
+        ("1 / 0.0", f64::INFINITY),
+    ] {
+        assert_eq!(eval(src), Value::Float(res), "{src}");
+    }
+    for (src, res) in [
+        ("1 == 1.0", true),
+        ("1.0 != 1", false),
+        ("1 < 1.5", true),
+        ("2.5 > 3", false),
+        ("9007199254740993 == 9007199254740992.0", true),
+    ] {
+        assert_eq!(eval(src), Value::Bool(res), "{src}");
+    }
+    // Ints alone stay ints
+    assert_eq!(eval("7 / 2"), Value::Int(3));
+    for (src, msg) in [
+        (
+            "1 + true",
+            "`<right of (+)>` mismatched: expect `int or float`, found `bool`",
+        ),
+        (
+            "\"a\" * 1.0",
+            "`<left of (*)>` mismatched: expect `int or float`, found `str`",
+        ),
+        ("1 == \"1\"", "`<right of (==)>`"),
+    ] {
+        let err = runtime_error(session().eval(src)).to_string();
+        assert!(err.contains(msg), "{src}: {err}");
+    }
+}
+
+#[test]
+fn test_conversions() {
+    for (src, res) in [
+        ("int(2.9)", 2),
+        ("int(-(2.9))", -2),
+        ("int(true)", 1),
+        ("int(\" 42 \")", 42),
+        ("int(-7)", -7),
+        ("parse_int(\"ff\", 16)", 255),
+        ("parse_int(\"-101\", 2)", -5),
+        ("parse_int(\"Zz\", 36)", 1295),
+    ] {
+        assert_eq!(eval(src), Value::Int(res), "{src}");
+    }
+    for (src, res) in [
+        ("float(2)", 2.0),
+        ("float(\"1.5\")", 1.5),
+        ("float(\"1e3\")", 1000.0),
+        ("float(\"-inf\")", f64::NEG_INFINITY),
+        ("float(int(3.7)) + 0.5", 3.5),
+    ] {
+        assert_eq!(eval(src), Value::Float(res), "{src}");
+    }
+    for (src, res) in [
+        ("str(12)", "12"),
+        ("str(-(1.5))", "-1.5"),
+        ("str(true)", "true"),
+        ("str(\"a\")", "a"),
+    ] {
+        assert_eq!(eval(src), Value::from(res), "{src}");
+    }
+    assert_eq!(eval("int(str(float(\"12\")))"), Value::Int(12));
+
+    for src in [
+        "int(\"1.5\")",
+        "int(\"\")",
+        "int(float(\"1e19\"))",
+        "int(float(\"nan\"))",
+        "float(\"one\")",
+        "parse_int(\"12\", 2)",
+    ] {
+        assert!(
+            matches!(
+                runtime_error(session().eval(src)),
+                RuntimeError::Conversion { .. }
+            ),
+            "{src}"
+        );
+    }
+    for src in ["parse_int(\"1\", 1)", "parse_int(\"1\", 37)"] {
+        assert!(
+            matches!(
+                runtime_error(session().eval(src)),
+                RuntimeError::InvalidRadix(_)
+            ),
+            "{src}"
+        );
+    }
+    for src in ["int([1])", "float(true)", "parse_int(12, 10)"] {
+        assert!(
+            matches!(
+                runtime_error(session().eval(src)),
+                RuntimeError::TypeError { .. }
+            ),
+            "{src}"
+        );
+    }
+}
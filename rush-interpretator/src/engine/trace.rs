@@ -0,0 +1,61 @@
+use std::{
+    fmt::{self, Display},
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A command about to be run, reported to the [`TraceSink`] when tracing is
+/// enabled.
+#[derive(Debug, Clone)]
+pub struct TraceEvent<'a> {
+    pub time: SystemTime,
+    /// The command after interpolation
+    pub cmd: &'a str,
+    /// Name of the script the command is in
+    pub file: &'a str,
+    /// Line of the command in the script, starting from 1
+    pub line: usize,
+    /// Column of the command in the script, starting from 1
+    pub col: usize,
+}
+
+impl Display for TraceEvent<'_> {
+    /// `+ [<unix seconds>.<millis>] <file>:<line>:<col> <cmd>`, like `set -x`
+    /// with a timestamp and location.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since_epoch = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        write!(
+            f,
+            "+ [{}.{:03}] {}:{}:{} {}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_millis(),
+            self.file,
+            self.line,
+            self.col,
+            self.cmd
+        )
+    }
+}
+
+/// Where command traces go.
+pub trait TraceSink {
+    fn trace(&self, event: &TraceEvent);
+}
+
+impl<F: Fn(&TraceEvent)> TraceSink for F {
+    fn trace(&self, event: &TraceEvent) {
+        self(event);
+    }
+}
+
+/// Writes each trace as one line to stderr, so it doesn't mix with the output
+/// of the script.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrTrace;
+
+impl TraceSink for StderrTrace {
+    fn trace(&self, event: &TraceEvent) {
+        // Tracing must never make the script fail
+        let _ = writeln!(std::io::stderr().lock(), "{event}");
+    }
+}
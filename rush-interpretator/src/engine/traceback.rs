@@ -0,0 +1,120 @@
+use std::fmt::{self, Display};
+
+use parser::{SourceMap, Span};
+
+/// Name of the outermost frame, running the script itself
+const SCRIPT: &str = "<script>";
+
+/// Frames repeated more than this many times in a row are folded, which keeps
+/// traces of runaway recursion short.
+const MAX_REPEAT: usize = 3;
+
+/// A function call an error went out of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Name the function was called by, which is also the name of its scope
+    pub name: String,
+    /// The call expression, `None` when called from native code
+    pub call_site: Option<Span>,
+    pub kind: FrameKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Native,
+    /// Script functions and closures
+    Script,
+}
+
+/// Stack trace of an error, displayed like a Python traceback, most recent
+/// call last:
+///
+/// ```text
+/// Traceback (most recent call last):
+///   File "script.rush", line 6, in <script>
+///     print(f(a));
+///   File "script.rush", line 2, in f
+///     let y = x + "s";
+/// ```
+///
+/// Each script frame is shown where it was left: at the call into the next
+/// frame, or at the error itself for the innermost one. Native frames have no
+/// source. An error that went through no function call has an empty
+/// traceback.
+#[derive(Debug, Clone)]
+pub struct Traceback<'a> {
+    sources: &'a SourceMap,
+    frames: &'a [Frame],
+    span: Option<Span>,
+}
+
+impl<'a> Traceback<'a> {
+    /// `frames` are innermost first, `span` is where the error is.
+    #[must_use]
+    pub const fn new(sources: &'a SourceMap, frames: &'a [Frame], span: Option<Span>) -> Self {
+        Self {
+            sources,
+            frames,
+            span,
+        }
+    }
+
+    /// One entry per frame, outermost first.
+    fn entries(&self) -> Vec<String> {
+        let callers = std::iter::once((SCRIPT, FrameKind::Script)).chain(
+            self.frames
+                .iter()
+                .rev()
+                .map(|frame| (frame.name.as_str(), frame.kind)),
+        );
+        let left_at = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| frame.call_site)
+            .chain(std::iter::once(self.span));
+        callers
+            .zip(left_at)
+            .filter_map(|((name, kind), at)| match kind {
+                FrameKind::Native => Some(format!("  In native function `{name}`")),
+                FrameKind::Script => self.entry(name, at?),
+            })
+            .collect()
+    }
+
+    fn entry(&self, name: &str, at: Span) -> Option<String> {
+        let file = self.sources.get(at.file)?;
+        let (line, _) = file.line_col(at.start);
+        Some(format!(
+            "  File \"{}\", line {line}, in {name}\n    {}",
+            file.name,
+            file.line(line).trim()
+        ))
+    }
+}
+
+impl Display for Traceback<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.frames.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "Traceback (most recent call last):")?;
+        let entries = self.entries();
+        let mut rest = entries.as_slice();
+        while let Some(entry) = rest.first() {
+            let repeated = rest.iter().take_while(|other| *other == entry).count();
+            for entry in &rest[..repeated.min(MAX_REPEAT)] {
+                writeln!(f, "{entry}")?;
+            }
+            if repeated > MAX_REPEAT {
+                writeln!(
+                    f,
+                    "  [Previous line repeated {} more times]",
+                    repeated - MAX_REPEAT
+                )?;
+            }
+            rest = &rest[repeated..];
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fmt::Display, ops::Deref, rc::Rc, sync::RwLock};
+use std::{cell::RefCell, fmt::Display, ops::Deref, rc::Rc, sync::RwLock};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Shared<T: ?Sized>(Rc<T>);
@@ -9,6 +9,19 @@ impl<T> Shared<T> {
     }
 }
 
+impl<T: ?Sized> Shared<T> {
+    #[must_use]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Address of the shared value, the same for every copy.
+    #[must_use]
+    pub fn as_ptr(&self) -> *const () {
+        Rc::as_ptr(&self.0).cast()
+    }
+}
+
 impl<T> Clone for Shared<T> {
     fn clone(&self) -> Self {
         Self(self.0.clone())
@@ -16,7 +29,7 @@ impl<T> Clone for Shared<T> {
 }
 
 impl<T: Display> Display for Shared<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         self.0.fmt(f)
     }
 }
@@ -49,7 +62,7 @@ impl<T> IntoShared for T {
 pub struct Locked<T>(RwLock<T>);
 
 impl<T> Locked<T> {
-    pub fn new(v: T) -> Self {
+    pub const fn new(v: T) -> Self {
         Self(RwLock::new(v))
     }
 
@@ -87,6 +100,35 @@ impl<T> From<T> for Shared<Locked<T>> {
     }
 }
 
+thread_local! {
+    static VISITING: RefCell<Vec<*const ()>> = const { RefCell::new(vec![]) };
+}
+
+/// Shared values being walked into, e.g. to print or compare a list, until
+/// dropped. A list can contain itself, so walking into a value that is
+/// already being walked would never end.
+pub(super) struct Visit(usize);
+
+impl Visit {
+    /// Start walking into the values at `ptrs`, `None` if one of them already
+    /// is.
+    pub(super) fn enter(ptrs: &[*const ()]) -> Option<Self> {
+        VISITING.with_borrow_mut(|visiting| {
+            if ptrs.iter().any(|ptr| visiting.contains(ptr)) {
+                return None;
+            }
+            visiting.extend_from_slice(ptrs);
+            Some(Self(ptrs.len()))
+        })
+    }
+}
+
+impl Drop for Visit {
+    fn drop(&mut self) {
+        VISITING.with_borrow_mut(|visiting| visiting.truncate(visiting.len() - self.0));
+    }
+}
+
 pub trait ToResult: Sized {
     fn err<T>(self) -> Result<T, Self>;
     fn ok<E>(self) -> Result<Self, E>;
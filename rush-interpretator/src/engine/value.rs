@@ -1,9 +1,13 @@
-use std::fmt::{self, Debug};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Debug},
+    hash::BuildHasher,
+};
 
 use parser::ast::{Literal, LiteralKind};
 use sealed::sealed;
 
-use crate::{FnRef, IntoShared, RuntimeError, RuntimeResult, Shared};
+use crate::{FnRef, IntoShared, List, Map, Range, RuntimeError, RuntimeResult, Shared};
 
 #[must_use]
 #[derive(Debug, Clone, PartialEq)]
@@ -13,6 +17,9 @@ pub enum Value {
     Bool(bool),
     Str(Shared<String>),
     Fn(FnRef),
+    Range(Range),
+    List(List),
+    Map(Map),
     Unit,
 }
 
@@ -56,6 +63,26 @@ impl Value {
         })
     }
 
+    /// Iterate over a range, a list or the `[key, value]` entries of a map.
+    /// Lists and maps are copied up front so the body of a loop is free to
+    /// modify them.
+    pub fn rt_iter(self, ident: &str) -> RuntimeResult<Box<dyn Iterator<Item = Self>>> {
+        match self {
+            Self::Range(range) => Ok(Box::new(range.into_iter().map(Self::Int))),
+            Self::List(list) => Ok(Box::new(list.to_vec().into_iter())),
+            Self::Map(map) => {
+                Ok(Box::new(map.to_map().into_iter().map(|(key, val)| {
+                    Self::from(vec![Self::Str(key.shared()), val])
+                })))
+            }
+            other => Err(RuntimeError::TypeError {
+                ident: ident.to_owned(),
+                expected: "range, list or map".to_owned(),
+                found: other.type_name().to_owned(),
+            }),
+        }
+    }
+
     pub fn rt_cast_ref<T: Variant>(&self, ident: &str) -> RuntimeResult<&T> {
         T::from_value_ref(self).map_err(|t| RuntimeError::TypeError {
             ident: ident.to_owned(),
@@ -65,32 +92,32 @@ impl Value {
     }
 }
 
-impl From<Literal<'_>> for Value {
+impl From<Literal> for Value {
     fn from(lit: Literal) -> Self {
         match lit.kind {
             LiteralKind::Number(val) => Self::Int(val),
             LiteralKind::Bool(b) => Self::Bool(b),
-            LiteralKind::String(s) => Self::Str(s.to_owned().shared()),
+            LiteralKind::String(s) => Self::Str(s.shared()),
             LiteralKind::Float(f) => Self::Float(f),
             l => unimplemented!("Literal type not implemented yet: {l:#?}"),
         }
     }
 }
 
-impl From<&Literal<'_>> for Value {
+impl From<&Literal> for Value {
     fn from(lit: &Literal) -> Self {
         match &lit.kind {
             LiteralKind::Number(val) => Self::Int(*val),
             LiteralKind::Bool(b) => Self::Bool(*b),
             LiteralKind::Float(f) => Self::Float(*f),
-            LiteralKind::String(s) => Self::Str((*s).to_owned().shared()),
+            LiteralKind::String(s) => Self::Str(s.clone().shared()),
             l => unimplemented!("Literal type not implemented yet: {l:#?}"),
         }
     }
 }
 
 impl fmt::Display for Value {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         map_value! {
             self,
             val => { fmt::Display::fmt(val, f) },
@@ -107,6 +134,9 @@ macro_rules! map_value {
             Value::Bool($id) => $act,
             Value::Str($id) => $act,
             Value::Fn($id) => $act,
+            Value::Range($id) => $act,
+            Value::List($id) => $act,
+            Value::Map($id) => $act,
             Value::Unit => $act2,
         }
     };
@@ -162,7 +192,7 @@ macro_rules! impl_varaint {
                 }
             }
 
-            fn from_value_ref<'b>(value: &'b Value) -> Result<&'b Self, &'b Value> {
+            fn from_value_ref(value: &Value) -> Result<&Self, &Value> {
                 match value {
                     Value::$variant(v) => Ok(v),
                     other => Err(other),
@@ -193,6 +223,87 @@ impl_varaint!(f64, Float, "float");
 impl_varaint!(bool, Bool, "bool");
 impl_varaint!(Shared<String>, Str, "str");
 impl_varaint!(FnRef, Fn, "fn");
+impl_varaint!(Range, Range, "range");
+impl_varaint!(List, List, "list");
+impl_varaint!(Map, Map, "map");
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Self::Str(s.shared())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Self::Str(s.to_owned().shared())
+    }
+}
+
+impl FromValue for String {
+    fn from_value(ident: &str, value: Value) -> RuntimeResult<Self> {
+        value.rt_cast::<Shared<Self>>(ident).map(|s| s.to_string())
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(_: &str, value: Value) -> RuntimeResult<Self> {
+        Ok(value)
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(ident: &str, value: Value) -> RuntimeResult<Self> {
+        value
+            .rt_cast::<List>(ident)?
+            .to_vec()
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| T::from_value(&format!("{ident}[{i}]"), item))
+            .collect()
+    }
+}
+
+impl<T: Into<Self>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        Self::List(List::new(items.into_iter().map(Into::into).collect()))
+    }
+}
+
+impl<T: FromValue> FromValue for BTreeMap<String, T> {
+    fn from_value(ident: &str, value: Value) -> RuntimeResult<Self> {
+        value
+            .rt_cast::<Map>(ident)?
+            .to_map()
+            .into_iter()
+            .map(|(key, val)| {
+                let val = T::from_value(&format!("{ident}[{key:?}]"), val)?;
+                Ok((key, val))
+            })
+            .collect()
+    }
+}
+
+impl<T: FromValue, S: BuildHasher + Default> FromValue for HashMap<String, T, S> {
+    fn from_value(ident: &str, value: Value) -> RuntimeResult<Self> {
+        BTreeMap::<String, T>::from_value(ident, value).map(|map| map.into_iter().collect())
+    }
+}
+
+impl<T: Into<Self>> From<BTreeMap<String, T>> for Value {
+    fn from(entries: BTreeMap<String, T>) -> Self {
+        Self::Map(Map::new(
+            entries.into_iter().map(|(k, v)| (k, v.into())).collect(),
+        ))
+    }
+}
+
+impl<T: Into<Self>, S: BuildHasher> From<HashMap<String, T, S>> for Value {
+    fn from(entries: HashMap<String, T, S>) -> Self {
+        Self::Map(Map::new(
+            entries.into_iter().map(|(k, v)| (k, v.into())).collect(),
+        ))
+    }
+}
 
 #[allow(clippy::module_name_repetitions)]
 pub trait FromValue: Sized {
@@ -27,7 +27,7 @@ impl Variable {
         &self.value
     }
 
-    pub fn value_mut(&mut self) -> &mut Value {
+    pub const fn value_mut(&mut self) -> &mut Value {
         &mut self.value
     }
 
@@ -1,19 +1,124 @@
 #![allow(clippy::module_name_repetitions)]
 
-use std::process::Output;
+use std::{path::PathBuf, process::Output};
 
+use parser::{Diagnostic, SourceMap, Span};
 use thiserror::Error;
 
-use crate::Ref;
+use crate::{Frame, Ref, Traceback};
 
 #[derive(Error, Debug)]
-pub enum Error<'src> {
-    #[error("{0}")]
-    Command(#[from] CommandError),
-    #[error("{0}")]
-    Runtime(#[from] RuntimeError),
-    #[error("{0}")]
-    Parse(parser::Error<'src>),
+pub enum Error {
+    #[error("{error}")]
+    Command {
+        error: CommandError,
+        /// The command that failed
+        span: Option<Span>,
+        /// Function calls the error went through, innermost first
+        trace: Vec<Frame>,
+    },
+    #[error("{error}")]
+    Runtime {
+        /// Boxed to keep [`Result`] small
+        error: Box<RuntimeError>,
+        /// The innermost expression or item that failed
+        span: Option<Span>,
+        /// Function calls the error went through, innermost first
+        trace: Vec<Frame>,
+    },
+    /// Every syntax error of a script, never empty
+    #[error("{}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))]
+    Parse(Vec<parser::Error>),
+}
+
+impl Error {
+    /// Locate the error at `span`, unless it's been located by a more precise
+    /// part of the script already.
+    #[must_use]
+    pub fn with_span(mut self, at: Span) -> Self {
+        match &mut self {
+            Self::Command { span, .. } | Self::Runtime { span, .. } => {
+                span.get_or_insert(at);
+            }
+            Self::Parse(_) => {}
+        }
+        self
+    }
+
+    /// Record that the error went out of a function call.
+    #[must_use]
+    pub fn with_frame(mut self, frame: Frame) -> Self {
+        match &mut self {
+            Self::Command { trace, .. } | Self::Runtime { trace, .. } => trace.push(frame),
+            Self::Parse(_) => {}
+        }
+        self
+    }
+
+    /// Function calls the error went through, innermost first.
+    #[must_use]
+    pub fn trace(&self) -> &[Frame] {
+        match self {
+            Self::Command { trace, .. } | Self::Runtime { trace, .. } => trace,
+            Self::Parse(_) => &[],
+        }
+    }
+
+    /// Where in the script the error is.
+    #[must_use]
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::Command { span, .. } | Self::Runtime { span, .. } => *span,
+            Self::Parse(errors) => errors.first().and_then(parser::Error::span),
+        }
+    }
+
+    /// The error pointing at the script it comes from, ready to be printed.
+    /// Only the first one of many syntax errors, see
+    /// [`diagnostics`](Self::diagnostics).
+    #[must_use]
+    pub fn diagnostic<'a>(&self, sources: &'a SourceMap) -> Diagnostic<'a> {
+        match self {
+            Self::Parse(errors) if !errors.is_empty() => errors[0].diagnostic(sources),
+            _ => Diagnostic::new(sources, self.to_string(), self.span()),
+        }
+    }
+
+    /// Like [`diagnostic`](Self::diagnostic), with one diagnostic per syntax
+    /// error.
+    #[must_use]
+    pub fn diagnostics<'a>(&self, sources: &'a SourceMap) -> Vec<Diagnostic<'a>> {
+        match self {
+            Self::Parse(errors) => errors.iter().map(|err| err.diagnostic(sources)).collect(),
+            _ => vec![self.diagnostic(sources)],
+        }
+    }
+
+    /// The stack trace of the error, ready to be printed.
+    #[must_use]
+    pub fn traceback<'a>(&'a self, sources: &'a SourceMap) -> Traceback<'a> {
+        Traceback::new(sources, self.trace(), self.span())
+    }
+}
+
+impl From<CommandError> for Error {
+    fn from(error: CommandError) -> Self {
+        Self::Command {
+            error,
+            span: None,
+            trace: Vec::new(),
+        }
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(error: RuntimeError) -> Self {
+        Self::Runtime {
+            error: Box::new(error),
+            span: None,
+            trace: Vec::new(),
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -32,10 +137,53 @@ pub enum RuntimeError {
         expected: usize,
         found: usize,
     },
+    #[error("Division by zero")]
+    DivisionByZero,
+    #[error("Integer overflow in `{left} {op} {right}`")]
+    Overflow {
+        op: &'static str,
+        left: i64,
+        right: i64,
+    },
+    #[error("Negative exponent {0} for an int")]
+    NegativeExponent(i64),
+    #[error("Cannot convert `{value}` to {to}: {reason}")]
+    Conversion {
+        value: String,
+        to: &'static str,
+        reason: String,
+    },
+    #[error("Invalid radix {0}, expected 2 to 36")]
+    InvalidRadix(i64),
+    #[error("Index {index} out of bounds for length {len}")]
+    IndexOutOfBounds { index: i64, len: usize },
+    #[error("Key `{0}` not found")]
+    KeyNotFound(String),
     #[error("Ref not found: `{0}`")]
     NullRefError(Ref),
     #[error("Max recursion depth exceeded")]
     MaxRecursionExceeded,
+    #[error("Cannot change directory to `{path}`: {reason}")]
+    ChangeDir { path: String, reason: String },
+    #[error("`return` outside of a function")]
+    ReturnOutsideFn,
+    #[error("`break` outside of a loop")]
+    BreakOutsideLoop,
+    #[error("`continue` outside of a loop")]
+    ContinueOutsideLoop,
+    #[error("Module `{name}` not found, looked for {}", quote_paths(searched))]
+    ModuleNotFound {
+        name: String,
+        searched: Vec<PathBuf>,
+    },
+    #[error("Cannot load module `{path}`: {reason}")]
+    ModuleLoad { path: String, reason: String },
+    /// Names of the modules importing each other, starting and ending with
+    /// the same one
+    #[error("Circular import: {}", .0.join(" -> "))]
+    CircularImport(Vec<String>),
+    #[error("`use` is only allowed at the top level of a script or module")]
+    NestedUse,
 }
 
 #[derive(Error, Debug)]
@@ -44,8 +192,34 @@ pub enum CommandError {
     Command(#[from] std::io::Error),
     #[error("Command output is not UTF-8")]
     CodingError(#[from] std::string::FromUtf8Error),
+    #[error("Command `{cmd}` failed with {}", code.map_or_else(
+        || "no exit code".to_owned(),
+        |code| format!("exit code {code}")
+    ))]
+    NonZeroExit { cmd: String, code: Option<i32> },
+    #[error("Cannot run `{program}`: {source}")]
+    Spawn {
+        program: String,
+        source: std::io::Error,
+    },
+    #[error("Invalid command: {0}")]
+    Syntax(String),
+    #[error("Cannot redirect to `{path}`: {source}")]
+    Redirect {
+        path: String,
+        source: std::io::Error,
+    },
+}
+
+/// `` `a`, `b` ``
+fn quote_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| format!("`{}`", path.display()))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
-pub type Result<'a, T> = std::result::Result<T, Error<'a>>;
+pub type Result<T> = std::result::Result<T, Error>;
 pub type RuntimeResult<T> = std::result::Result<T, RuntimeError>;
 pub type CommandResult<O = Output> = std::result::Result<O, CommandError>;
@@ -6,57 +6,61 @@
 
 mod_use::mod_use![engine, error];
 
+mod builtin;
+#[cfg(feature = "bin")]
+mod repl;
+
 #[cfg(feature = "bin")]
 pub fn run() -> color_eyre::Result<()> {
-    use color_eyre::eyre::{Context as EyreContext, ContextCompat};
-    use parser::parse;
-
-    #[allow(clippy::unnecessary_wraps)]
-    fn print(args: Vec<Value>) -> RuntimeResult<Value> {
-        let mut iter = args.into_iter().peekable();
-        loop {
-            let arg = iter.next().unwrap();
-            if iter.peek().is_none() {
-                print!("{}", arg);
-                break;
-            }
-            print!("{} ", arg);
-        }
-        Ok(Value::Unit)
-    }
+    use color_eyre::eyre::Context as EyreContext;
 
-    #[allow(clippy::needless_pass_by_value)]
-    fn type_of(args: Vec<Value>) -> RuntimeResult<Value> {
-        if args.len() != 1 {
-            return Err(RuntimeError::ArgumentError {
-                ident: "typeof".to_string(),
-                expected: 1,
-                found: args.len(),
-            });
+    color_eyre::install().unwrap();
+
+    let mut args = std::env::args().skip(1).peekable();
+    let mut engine = Engine::new();
+    while let Some(flag) = args.next_if(|arg| arg.starts_with('-')) {
+        match flag.as_str() {
+            // Trace every command to stderr, like `set -x`
+            "-x" => engine = engine.with_trace(StderrTrace),
+            // Run commands with `sh -c`
+            "--sh" => engine = engine.use_shell(true),
+            _ => color_eyre::eyre::bail!("Unknown flag `{flag}`"),
         }
-        Ok(Value::Str(args[0].type_name().to_owned().shared()))
     }
+    // Module search path, like `PYTHONPATH`
+    let loader = std::env::var_os("RUSH_PATH")
+        .iter()
+        .flat_map(std::env::split_paths)
+        .fold(FileLoader::new(), FileLoader::with_dir);
+    engine = engine.with_module_loader(loader);
+    let engine = engine
+        .with_builtins()
+        .with_fn("add", |a: i64, b: i64| Ok(Value::Int(a + b)))
+        .with_fn("minus", |a: i64, b: i64| Ok((a - b).into()))
+        .with_module(
+            Module::new("math")
+                .with_fn("max", |a: i64, b: i64| Ok(a.max(b).into()))
+                .with_fn("min", |a: i64, b: i64| Ok(a.min(b).into()))
+                .with_const("pi", std::f64::consts::PI),
+        );
 
-    color_eyre::install().unwrap();
-
-    let path = std::env::args()
-        .nth(1)
-        .wrap_err_with(|| format!("Usage: {} <path>", std::env::args().next().unwrap()))?;
+    // No script to run, start a REPL
+    let Some(path) = args.next() else {
+        return repl::run(engine).wrap_err("Failed to read input");
+    };
 
     let src = std::fs::read_to_string(&path).wrap_err("Failed to load source file")?;
 
-    Engine::new()
-        .with_fn("add", |a: i64, b: i64| Ok(Value::Int(a + b)))
-        .with_fn("minus", |a: i64, b: i64| Ok((a - b).into()))
-        .with_fn_raw("print", print)
-        .with_fn_raw("println", |args| {
-            let ret = print(args)?;
-            println!();
-            Ok(ret)
-        })
-        .with_fn_raw("type_of", type_of)
-        .execute(&src)
-        .unwrap();
+    let mut ctx = engine
+        .into_context()
+        .map_err(|err| color_eyre::eyre::eyre!("{err}"))?;
+    if let Err(err) = ctx.load(&path, src).and_then(|tree| ctx.run(&tree)) {
+        eprint!("{}", err.traceback(ctx.sources()));
+        for diagnostic in err.diagnostics(ctx.sources()) {
+            eprintln!("{diagnostic}");
+        }
+        std::process::exit(1);
+    }
 
     Ok(())
 }
@@ -0,0 +1,88 @@
+//! Interactive mode of the `rush` binary.
+
+use std::io::{self, BufRead, Write};
+
+use parser::CodeChars;
+
+use crate::{Engine, Value};
+
+#[cfg(test)]
+mod test;
+
+const PROMPT: &str = "rush> ";
+const CONTINUE_PROMPT: &str = "  ... ";
+
+/// Read scripts from stdin and run them one after another in the same context,
+/// until EOF. Input is read until all brackets are closed, the value of a
+/// trailing expression is printed and errors are reported without leaving.
+pub fn run(engine: Engine) -> io::Result<()> {
+    let mut session = match engine.session() {
+        Ok(session) => session,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            return Ok(());
+        }
+    };
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        let Some(src) = read_chunk(&mut lines)? else {
+            println!();
+            return Ok(());
+        };
+        if src.trim().is_empty() {
+            continue;
+        }
+        match session.eval(&src) {
+            Ok(Value::Unit) => {}
+            Ok(val) => println!("{val}"),
+            Err(err) => {
+                let sources = session.context().sources();
+                eprint!("{}", err.traceback(sources));
+                for diagnostic in err.diagnostics(sources) {
+                    eprintln!("{diagnostic}");
+                }
+            }
+        }
+    }
+}
+
+/// Read lines until brackets balance. `None` on EOF before any input.
+fn read_chunk(lines: &mut impl Iterator<Item = io::Result<String>>) -> io::Result<Option<String>> {
+    let mut chunk = String::new();
+    let mut prompt = PROMPT;
+    loop {
+        print!("{prompt}");
+        io::stdout().flush()?;
+        let Some(line) = lines.next().transpose()? else {
+            return Ok((!chunk.is_empty()).then_some(chunk));
+        };
+        chunk.push_str(&line);
+        chunk.push('\n');
+        if depth(&chunk) <= 0 {
+            return Ok(Some(chunk));
+        }
+        prompt = CONTINUE_PROMPT;
+    }
+}
+
+/// Number of brackets left open in `src`, not counting the ones in strings,
+/// commands and comments.
+fn depth(src: &str) -> isize {
+    let mut depth = 0;
+    let mut chars = CodeChars::new(src);
+    for (_, c) in chars.by_ref() {
+        match c {
+            '{' | '[' | '(' => depth += 1,
+            '}' | ']' | ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    if chars.unclosed() {
+        // Keep reading
+        depth + 1
+    } else {
+        depth
+    }
+}
@@ -0,0 +1,44 @@
+use std::io;
+
+use super::{depth, read_chunk};
+
+fn chunks(lines: &[&str]) -> Vec<String> {
+    let mut lines = lines.iter().map(|line| io::Result::Ok((*line).to_owned()));
+    std::iter::from_fn(|| read_chunk(&mut lines).unwrap()).collect()
+}
+
+#[test]
+fn test_depth() {
+    assert_eq!(depth("let x = 1;"), 0);
+    assert_eq!(depth("fn f() {\n  [(1"), 3);
+    assert_eq!(depth("{ [ ( ) ] }"), 0);
+    assert_eq!(depth("}"), -1);
+    // Brackets in strings, commands and comments don't count
+    assert_eq!(depth(r#"let s = "{(\"["; // {"#), 0);
+    assert_eq!(depth("$`echo }` {"), 1);
+    assert_eq!(depth(r#"print("${ "}" } ${ #{ a: [1] }.a }")"#), 0);
+    assert_eq!(depth(r#"if "${ "{" }" == x {"#), 1);
+    // Strings, commands and interpolations can go on over lines
+    assert!(depth("let s = \"a\n") > 0);
+    assert!(depth("$`ls\n") > 0);
+    assert!(depth("print(\"${ f(\n") > 0);
+}
+
+#[test]
+fn test_read_chunk() {
+    assert_eq!(
+        chunks(&["let x = 1;", "fn f() {", "  \"}\" // }", "}", "f()"]),
+        ["let x = 1;\n", "fn f() {\n  \"}\" // }\n}\n", "f()\n"]
+    );
+    assert_eq!(
+        chunks(&["let s = \"a ${", "  \"}\" + x", "} b\";", "$`echo", "a`"]),
+        ["let s = \"a ${\n  \"}\" + x\n} b\";\n", "$`echo\na`\n"]
+    );
+    assert_eq!(
+        chunks(&["let xs = [", "1,", "];", ""]),
+        ["let xs = [\n1,\n];\n", "\n"]
+    );
+    // What's left at EOF is a chunk of its own
+    assert_eq!(chunks(&["if true {", "1"]), ["if true {\n1\n"]);
+    assert!(chunks(&[]).is_empty());
+}
@@ -1,123 +1,206 @@
-use std::{hash::Hash, marker::PhantomData};
+use std::hash::Hash;
 
-use pest::Span;
+use crate::Span;
 
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct Tree<'src> {
-    pub span: Span<'src>,
-    pub items: Vec<Item<'src>>,
+pub struct Tree {
+    pub span: Span,
+    pub items: Vec<Item>,
+    /// Trailing expression without semicolon, which is the value of the script
+    pub expr: Option<Box<Expr>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct Item<'src> {
-    pub span: Span<'src>,
-    pub kind: ItemKind<'src>,
+pub struct Item {
+    pub span: Span,
+    pub kind: ItemKind,
 }
 
 #[non_exhaustive]
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub enum ItemKind<'src> {
-    FnDef(FnDef<'src>),
-    Stmt(Stmt<'src>),
-    Assign(Assign<'src>),
-    If(If<'src>),
-    For(For<'src>),
-    While(While<'src>),
-    Expr(Expr<'src>),
+pub enum ItemKind {
+    FnDef(FnDef),
+    Stmt(Stmt),
+    Assign(Assign),
+    EnvAssign(EnvAssign),
+    WithEnv(WithEnv),
+    Within(Within),
+    If(If),
+    For(For),
+    While(While),
+    Return(Return),
+    Break(Break),
+    Continue(Continue),
+    Use(Use),
+    Expr(Expr),
 }
 
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct Stmt<'src> {
-    pub span: Span<'src>,
-    pub ident: Ident<'src>,
-    pub expr: Expr<'src>,
+pub struct Stmt {
+    pub span: Span,
+    pub ident: Ident,
+    pub expr: Expr,
 }
 
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct FnDef<'src> {
-    pub span: Span<'src>,
-    pub ident: Ident<'src>,
-    pub params: Vec<Ident<'src>>,
-    pub body: Block<'src>,
+pub struct FnDef {
+    pub span: Span,
+    pub ident: Ident,
+    pub params: Vec<Ident>,
+    pub body: Block,
 }
 
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct If<'src> {
-    pub span: Span<'src>,
-    pub cond: Expr<'src>,
-    pub then_block: Block<'src>,
-    pub else_block: Option<Block<'src>>,
+pub struct If {
+    pub span: Span,
+    pub cond: Expr,
+    pub then_block: Block,
+    pub else_block: Option<Block>,
 }
 
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct For<'src> {
-    pub span: Span<'src>,
-    pub ident: Ident<'src>,
-    pub expr: Expr<'src>,
-    pub block: Block<'src>,
+pub struct For {
+    pub span: Span,
+    pub ident: Ident,
+    pub expr: Expr,
+    pub block: Block,
 }
 
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct While<'src> {
-    pub span: Span<'src>,
-    pub expr: Expr<'src>,
-    pub block: Block<'src>,
+pub struct While {
+    pub span: Span,
+    pub expr: Expr,
+    pub block: Block,
 }
 
+/// `with_env vars { ... }`, where `vars` is a map of env vars set only for the
+/// commands run inside of the block
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct Expr<'src> {
-    pub kind: ExprKind<'src>,
-    pub span: Span<'src>,
+pub struct WithEnv {
+    pub span: Span,
+    pub vars: Expr,
+    pub block: Block,
+}
+
+/// `within dir { ... }`, where commands inside of the block run in `dir`
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct Within {
+    pub span: Span,
+    pub dir: Expr,
+    pub block: Block,
+}
+
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct Return {
+    pub span: Span,
+    pub expr: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct Break {
+    pub span: Span,
+    pub expr: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct Continue {
+    pub span: Span,
+}
+
+/// `use name;`, which loads the module `name` and makes its globals
+/// reachable as `name::global`
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct Use {
+    pub span: Span,
+    pub ident: Ident,
+}
+
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
 }
 
 #[non_exhaustive]
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub enum ExprKind<'src> {
-    Ident(Ident<'src>),
-    Literal(Literal<'src>),
-    FnCall(FnCall<'src>),
-    Exec(Exec<'src>),
-    Block(Block<'src>),
-    BinOp(BinOpExpr<'src>),
-    UnOp(UnOpExpr<'src>),
+pub enum ExprKind {
+    Ident(Ident),
+    Literal(Literal),
+    FnCall(FnCall),
+    Exec(Exec),
+    /// `$NAME`, value of an env var
+    EnvVar(Ident),
+    Template(Template),
+    Block(Block),
+    BinOp(BinOpExpr),
+    UnOp(UnOpExpr),
+    Range(RangeExpr),
+    List(ListExpr),
+    Map(MapExpr),
+    Closure(ClosureExpr),
+    Index(IndexExpr),
     Unit,
 }
 
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct Ident<'src> {
-    pub name: &'src str,
-    pub span: Span<'src>,
+pub struct Ident {
+    /// The name, or `module::name` for a global of an imported module
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct FnCall {
+    pub ident: Ident,
+    pub args: Vec<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct Exec {
+    pub span: Span,
+    /// Source text between the backquotes
+    pub cmd: String,
+    pub parts: Vec<TemplatePart>,
+    /// Written as ``$?`cmd` ``, evaluating to exit status, stdout and stderr
+    /// instead of stdout only
+    pub structured: bool,
 }
 
+/// String literal containing interpolations, e.g. `"hello ${name}"`
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct FnCall<'src> {
-    pub ident: Ident<'src>,
-    pub args: Vec<Expr<'src>>,
-    pub span: Span<'src>,
+pub struct Template {
+    pub span: Span,
+    pub parts: Vec<TemplatePart>,
 }
 
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct Exec<'src> {
-    pub span: Span<'src>,
-    pub cmd: &'src str,
+pub enum TemplatePart {
+    Text(String),
+    /// `${expr}`, shell quoted when used in exec
+    Interp(Expr),
+    /// `$!{expr}`, spliced as is
+    RawInterp(Expr),
 }
 
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct Block<'src> {
-    pub span: Span<'src>,
-    pub items: Vec<Item<'src>>,
+pub struct Block {
+    pub span: Span,
+    pub items: Vec<Item>,
+    /// Trailing expression without semicolon, which is the value of the block
+    pub expr: Option<Box<Expr>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct Literal<'src> {
-    pub kind: LiteralKind<'src>,
-    pub span: Span<'src>,
+pub struct Literal {
+    pub kind: LiteralKind,
+    pub span: Span,
 }
 
 #[non_exhaustive]
 #[derive(Debug, Clone, PartialEq)]
-pub enum LiteralKind<'src> {
-    String(&'src str),
+pub enum LiteralKind {
+    String(String),
     Bool(bool),
     Number(i64),
     Float(f64),
@@ -125,12 +208,16 @@ pub enum LiteralKind<'src> {
 
 #[non_exhaustive]
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub enum BinOpKind<'src> {
-    __Marker(PhantomData<&'src ()>),
+pub enum BinOpKind {
     Add,
     Sub,
     Mul,
+    /// Floor division for ints, which rounds towards negative infinity:
+    /// `-7 / 2 == -4`
     Div,
+    /// Remainder of floor division, which has the sign of the divisor
+    Rem,
+    Pow,
     Eq,
     Neq,
     Lt,
@@ -150,24 +237,79 @@ pub enum UnOpKind {
 
 #[non_exhaustive]
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct BinOpExpr<'src> {
-    pub left: Box<Expr<'src>>,
-    pub right: Box<Expr<'src>>,
-    pub kind: BinOpKind<'src>,
-    pub span: Span<'src>,
+pub struct BinOpExpr {
+    pub left: Box<Expr>,
+    pub right: Box<Expr>,
+    pub kind: BinOpKind,
+    pub span: Span,
 }
 
 #[non_exhaustive]
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct UnOpExpr<'src> {
-    pub expr: Box<Expr<'src>>,
+pub struct UnOpExpr {
+    pub expr: Box<Expr>,
     pub kind: UnOpKind,
-    pub span: Span<'src>,
+    pub span: Span,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct RangeExpr {
+    pub start: Box<Expr>,
+    pub end: Box<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct ListExpr {
+    pub items: Vec<Expr>,
+    pub span: Span,
+}
+
+/// Anonymous function, either `|a, b| expr` or `fn(a, b) { ... }`
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct ClosureExpr {
+    pub params: Vec<Ident>,
+    pub body: Box<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct MapExpr {
+    pub entries: Vec<MapEntry>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct MapEntry {
+    pub key: String,
+    pub expr: Expr,
+    pub span: Span,
+}
+
+/// `expr[index]`. Field access `expr.name` is parsed into an index with the
+/// string literal `name`.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct IndexExpr {
+    pub expr: Box<Expr>,
+    pub index: Box<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct Assign {
+    pub span: Span,
+    pub ident: Ident,
+    /// Indices applied to `ident` before assigning, e.g. `xs[0].name = 1;`
+    pub indices: Vec<Expr>,
+    pub expr: Expr,
 }
 
+/// `$NAME = expr;`, assigning `()` unsets the env var
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct Assign<'src> {
-    pub span: Span<'src>,
-    pub ident: Ident<'src>,
-    pub expr: Expr<'src>,
+pub struct EnvAssign {
+    pub span: Span,
+    pub ident: Ident,
+    pub expr: Expr,
 }
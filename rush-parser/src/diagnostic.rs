@@ -0,0 +1,61 @@
+use std::fmt;
+
+use crate::{SourceMap, Span};
+
+/// An error message pointing at the source it is about, displayed like
+///
+/// ```text
+/// error: Identifier `b` not found
+///  --> script.rush:2:13
+///   |
+/// 2 | let a = 1 + b;
+///   |             ^
+/// ```
+///
+/// Without a span, or with a span of an unknown file, only the message is
+/// shown.
+#[derive(Debug, Clone)]
+pub struct Diagnostic<'a> {
+    pub message: String,
+    pub span: Option<Span>,
+    sources: &'a SourceMap,
+}
+
+impl<'a> Diagnostic<'a> {
+    #[must_use]
+    pub fn new(sources: &'a SourceMap, message: impl Into<String>, span: Option<Span>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            sources,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error: {}", self.message)?;
+        let Some((span, file)) = self
+            .span
+            .and_then(|span| Some((span, self.sources.get(span.file)?)))
+        else {
+            return Ok(());
+        };
+
+        let (line, col) = file.line_col(span.start);
+        let text = file.line(line);
+        // Only the first line of a span spreading over many is underlined
+        let width = file.src[span.start.min(file.src.len())..span.end.min(file.src.len())]
+            .lines()
+            .next()
+            .map_or(0, |first| first.chars().count())
+            .max(1);
+        let gutter = " ".repeat(line.to_string().len());
+
+        writeln!(f)?;
+        writeln!(f, "{gutter}--> {}:{line}:{col}", file.name)?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{line} | {text}")?;
+        write!(f, "{gutter} | {}{}", " ".repeat(col - 1), "^".repeat(width))
+    }
+}
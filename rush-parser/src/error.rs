@@ -1,25 +1,191 @@
-use pest::Span;
+use pest::error::{ErrorVariant, InputLocation};
 
-use crate::Rule;
+use crate::{Diagnostic, FileId, Rule, SourceMap, Span};
 
 #[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
-pub enum Error<'src> {
-    #[error("Parse error: {0}")]
-    Pest(#[from] pest::error::Error<Rule>),
-    #[error("Tree error: expect {expect:?}, found {found:?}, at {span:?}")]
+pub enum Error {
+    /// Input not matching the grammar
+    #[error("Syntax error: {}", syntax_message(expected, found))]
+    Syntax {
+        /// Rules that could have matched, empty if nothing could
+        expected: Vec<Rule>,
+        /// What was found instead, like `` `;` `` or `end of input`
+        found: String,
+        span: Span,
+    },
+    #[error("Syntax error: expected {}, found {}", describe_all(expect), describe(*found))]
     TreeError {
         expect: Vec<Rule>,
         found: Rule,
-        span: Span<'src>,
+        span: Span,
     },
-    #[error("Literal format error: expect {expect:?}, found {val:?}, at {span:?}")]
+    #[error("Invalid literal `{val}`, expected {}", describe(*expect))]
     LiteralError {
         expect: Rule,
-        val: &'src str,
-        span: Span<'src>,
+        val: String,
+        span: Span,
     },
     #[error("Invalid input: {0}")]
-    InputInvalid(&'src str),
+    InputInvalid(String),
+}
+
+impl Error {
+    /// Convert an error of pest parsing `input`, the source of `file`.
+    pub(crate) fn from_pest(err: &pest::error::Error<Rule>, input: &str, file: FileId) -> Self {
+        let (start, end) = match err.location {
+            InputLocation::Pos(pos) => (pos, pos),
+            InputLocation::Span(span) => span,
+        };
+        let expected = match &err.variant {
+            ErrorVariant::ParsingError { positives, .. } => positives.clone(),
+            ErrorVariant::CustomError { .. } => vec![],
+        };
+        Self::Syntax {
+            expected,
+            found: found_at(input, start),
+            span: Span::new(file, start, end),
+        }
+    }
+
+    /// Where in the source the error is.
+    #[must_use]
+    pub const fn span(&self) -> Option<Span> {
+        match self {
+            Self::Syntax { span, .. }
+            | Self::TreeError { span, .. }
+            | Self::LiteralError { span, .. } => Some(*span),
+            Self::InputInvalid(_) => None,
+        }
+    }
+
+    /// The error pointing at its source, ready to be printed.
+    #[must_use]
+    pub fn diagnostic<'a>(&self, sources: &'a SourceMap) -> Diagnostic<'a> {
+        Diagnostic::new(sources, self.to_string(), self.span())
+    }
+}
+
+fn syntax_message(expected: &[Rule], found: &str) -> String {
+    if expected.is_empty() {
+        format!("unexpected {found}")
+    } else {
+        format!("expected {}, found {found}", describe_all(expected))
+    }
+}
+
+/// The token starting at `pos`: a whole word, or a single char.
+fn found_at(input: &str, pos: usize) -> String {
+    let rest = input.get(pos..).unwrap_or_default();
+    let word_len = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    match rest.chars().next() {
+        None => "end of input".to_owned(),
+        Some('\n' | '\r') => "end of line".to_owned(),
+        Some(_) if word_len > 0 => format!("`{}`", &rest[..word_len]),
+        Some(c) => format!("`{c}`"),
+    }
+}
+
+/// `a`, `a or b`, `a, b or c`, without repeating the rules that read the same.
+fn describe_all(rules: &[Rule]) -> String {
+    let mut names: Vec<&str> = vec![];
+    for name in rules.iter().map(|rule| describe(*rule)) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    match names.split_last() {
+        None => "nothing".to_owned(),
+        Some((last, [])) => (*last).to_owned(),
+        Some((last, rest)) => format!("{} or {last}", rest.join(", ")),
+    }
+}
+
+/// What a rule looks like in the source, for error messages.
+const fn describe(rule: Rule) -> &'static str {
+    match rule {
+        Rule::expr | Rule::trivial_expr | Rule::bin_op_expr | Rule::range | Rule::un_op_expr => {
+            "an expression"
+        }
+        Rule::item => "an item",
+        Rule::ident | Rule::path => "an identifier",
+        Rule::ident_list => "a parameter list",
+        Rule::expr_list => "an argument list",
+        Rule::bin_op | Rule::op => "an operator",
+        Rule::un_op => "`-` or `!`",
+        Rule::index => "`[`",
+        Rule::field => "`.`",
+        Rule::block => "a block",
+        Rule::unit => "`()`",
+        Rule::paren => "a parenthesised expression",
+        Rule::literal => "a literal",
+        Rule::string => "a string",
+        Rule::number => "a number",
+        Rule::float => "a float",
+        Rule::bool => "`true` or `false`",
+        Rule::list => "a list",
+        Rule::map => "a map",
+        Rule::map_entry => "a map entry",
+        Rule::closure => "a closure",
+        Rule::fn_call => "a function call",
+        Rule::exec => "a command",
+        Rule::env_var => "an env var",
+        Rule::str_text | Rule::char => "string text",
+        Rule::exec_text => "command text",
+        Rule::interp | Rule::raw_interp | Rule::escaped_interp => "an interpolation",
+        Rule::stmt => "a `let` statement",
+        Rule::assign | Rule::env_assign => "an assignment",
+        Rule::fn_def => "a function definition",
+        Rule::if_loop => "`if`",
+        Rule::for_loop => "`for`",
+        Rule::while_loop => "`while`",
+        Rule::with_env => "`with_env`",
+        Rule::within => "`within`",
+        Rule::return_item => "`return`",
+        Rule::break_item => "`break`",
+        Rule::continue_item => "`continue`",
+        Rule::use_item => "`use`",
+        Rule::keyword => "a keyword",
+        Rule::main => "a script",
+        Rule::EOI => "end of input",
+        Rule::WHITESPACE | Rule::multispace => "whitespace",
+        Rule::COMMENT => "a comment",
+        Rule::not_digit => "no digit",
+        Rule::add => "`+`",
+        Rule::sub | Rule::neg => "`-`",
+        Rule::mul => "`*`",
+        Rule::div => "`/`",
+        Rule::rem => "`%`",
+        Rule::pow => "`**`",
+        Rule::and => "`&&`",
+        Rule::or => "`||`",
+        Rule::eq => "`==`",
+        Rule::neq => "`!=`",
+        Rule::lt => "`<`",
+        Rule::gt => "`>`",
+        Rule::ge => "`>=`",
+        Rule::le => "`<=`",
+        Rule::not => "`!`",
+        Rule::dot => "`.`",
+        Rule::colon | Rule::colon_token => "`:`",
+        Rule::comma | Rule::comma_token => "`,`",
+        Rule::quote => "`\"`",
+        Rule::dollar => "`$`",
+        Rule::backquote => "`` ` ``",
+        Rule::left_paren | Rule::left_paren_token => "`(`",
+        Rule::right_paren | Rule::right_paren_token => "`)`",
+        Rule::left_brace | Rule::left_brace_token => "`{`",
+        Rule::right_brace | Rule::right_brace_token => "`}`",
+        Rule::left_bracket | Rule::left_bracket_token => "`[`",
+        Rule::right_bracket | Rule::right_bracket_token => "`]`",
+        Rule::semicolon | Rule::semicolon_token => "`;`",
+        Rule::underscore => "`_`",
+        Rule::pipe => "`|`",
+        Rule::interp_start => "`${`",
+        Rule::exec_start => "`` $` ``",
+        Rule::exec_status => "`?`",
+    }
 }
 
-pub type Result<'a, T> = std::result::Result<T, Error<'a>>;
+pub type Result<T> = std::result::Result<T, Error>;
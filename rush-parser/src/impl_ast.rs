@@ -1,90 +1,121 @@
-use std::{fmt, hash::Hash};
+use std::{fmt, hash::Hash, sync::LazyLock};
 
 use itertools::Itertools;
-use pest::{iterators::Pair, Span};
+use pest::{
+    iterators::Pair,
+    pratt_parser::{Assoc, Op, PrattParser},
+};
 
 use crate::{
     ast::*,
     error::{Error, Result},
-    Rule,
+    FileId, Rule, Span,
 };
 
-pub trait Node<'src> {
+pub trait Node {
     const RULE: Rule;
-    fn span(&self) -> &Span<'src>;
-    fn src(&self) -> &'src str;
+    fn span(&self) -> Span;
 }
 
-macro_rules! impl_node {
-    ($ty:ident, $def_rule:ident $(, $extra_rule:ident )* $(,)? => $val:ident => $constructor:expr) => {
-        impl<'src> TryFrom<Pair<'src, Rule>> for $ty<'src> {
-            type Error = Error<'src>;
+/// Conversion of a pair parsed from `file` into an owned node.
+pub(crate) trait FromPair: Sized {
+    fn from_pair(pair: Pair<'_, Rule>, file: FileId) -> Result<Self>;
+}
 
+macro_rules! impl_node {
+    ($ty:ident, $def_rule:ident $(, $extra_rule:ident )* $(,)? => $val:ident, $file:ident => $constructor:expr) => {
+        impl FromPair for $ty {
             #[allow(unused_mut)]
-            fn try_from(mut $val: Pair<'src, Rule>) -> Result<Self> {
-                ensure!($val, $def_rule, $( $extra_rule, )*);
+            fn from_pair(mut $val: Pair<'_, Rule>, $file: FileId) -> Result<Self> {
+                ensure!($val in $file, $def_rule, $( $extra_rule, )*);
 
                 Ok($constructor)
             }
         }
 
-        impl<'src> Node<'src> for $ty<'src> {
+        impl Node for $ty {
             const RULE: Rule = Rule::$def_rule;
 
-            fn span(&self) -> &Span<'src> {
-                &self.span
-            }
-
-            fn src(&self) -> &'src str {
-                self.span().as_str()
+            fn span(&self) -> Span {
+                self.span
             }
         }
     };
 }
 
 macro_rules! ensure {
-    ($val:ident, $first:ident $(, $expect:ident )* $(,)?) => {
+    ($val:ident in $file:ident, $first:ident $(, $expect:ident )* $(,)?) => {
         let rule = $val.as_rule();
         if rule != Rule::$first $( && rule != Rule::$expect )* {
             return Err(Error::TreeError {
                 expect: vec![Rule::$first, $( Rule::$expect, )*],
                 found: $val.as_rule(),
-                span: $val.as_span(),
+                span: Span::from_pest(&$val.as_span(), $file),
             });
         }
     };
 }
 
 impl_node! {
-    Tree, main => value => {
-        let span = value.as_span();
-        let iter = value.into_inner();
-        let items = iter.map_while(|x| {
-            match x.as_rule() {
-                Rule::item => Some(Item::try_from(x)),
-                Rule::EOI => None,
-                _ => unreachable!("Tree should only contain item and EOI"),
-            }
-        }).try_collect()?;
-        Tree {
-            span,
-            items,
+    Tree, main => value, file => {
+        let mut errors = vec![];
+        let tree = Tree::from_pair_lossy(value, file, &mut errors);
+        if let Some(err) = errors.into_iter().next() {
+            return Err(err);
         }
+        tree
+    }
+}
+
+impl Tree {
+    /// Convert a `main` pair, leaving out the items that fail to convert and
+    /// pushing their errors to `errors`.
+    pub(crate) fn from_pair_lossy(
+        value: Pair<'_, Rule>,
+        file: FileId,
+        errors: &mut Vec<Error>,
+    ) -> Self {
+        let span = Span::from_pest(&value.as_span(), file);
+        let mut iter = value.into_inner().peekable();
+        let items = iter
+            .peeking_take_while(|x| x.as_rule() == Rule::item)
+            .filter_map(|pair| {
+                Item::from_pair(pair, file)
+                    .map_err(|err| errors.push(err))
+                    .ok()
+            })
+            .collect();
+        let expr = match iter.next() {
+            Some(x) if x.as_rule() == Rule::expr => Expr::from_pair(x, file)
+                .map_err(|err| errors.push(err))
+                .ok()
+                .map(Box::new),
+            Some(x) if x.as_rule() == Rule::EOI => None,
+            _ => unreachable!("Tree should only contain item, expr and EOI"),
+        };
+        Self { span, items, expr }
     }
 }
 
 impl_node! {
-    Item, item => value => {
-        let span = value.as_span();
+    Item, item => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
         let inner = value.into_inner().next().expect("Item should have content");
         let kind = match inner.as_rule() {
-            Rule::fn_def => ItemKind::FnDef(FnDef::try_from(inner)?),
-            Rule::stmt => ItemKind::Stmt(Stmt::try_from(inner)?),
-            Rule::assign => ItemKind::Assign(Assign::try_from(inner)?),
-            Rule::if_loop => ItemKind::If(If::try_from(inner)?),
-            Rule::for_loop => ItemKind::For(For::try_from(inner)?),
-            Rule::while_loop => ItemKind::While(While::try_from(inner)?),
-            Rule::expr => ItemKind::Expr(Expr::try_from(inner)?),
+            Rule::fn_def => ItemKind::FnDef(FnDef::from_pair(inner, file)?),
+            Rule::stmt => ItemKind::Stmt(Stmt::from_pair(inner, file)?),
+            Rule::assign => ItemKind::Assign(Assign::from_pair(inner, file)?),
+            Rule::env_assign => ItemKind::EnvAssign(EnvAssign::from_pair(inner, file)?),
+            Rule::with_env => ItemKind::WithEnv(WithEnv::from_pair(inner, file)?),
+            Rule::within => ItemKind::Within(Within::from_pair(inner, file)?),
+            Rule::if_loop => ItemKind::If(If::from_pair(inner, file)?),
+            Rule::for_loop => ItemKind::For(For::from_pair(inner, file)?),
+            Rule::while_loop => ItemKind::While(While::from_pair(inner, file)?),
+            Rule::return_item => ItemKind::Return(Return::from_pair(inner, file)?),
+            Rule::break_item => ItemKind::Break(Break::from_pair(inner, file)?),
+            Rule::continue_item => ItemKind::Continue(Continue::from_pair(inner, file)?),
+            Rule::use_item => ItemKind::Use(Use::from_pair(inner, file)?),
+            Rule::expr => ItemKind::Expr(Expr::from_pair(inner, file)?),
             _ => unreachable!("Item should have expr, stmt or fn_def"),
         };
         Item {
@@ -95,13 +126,15 @@ impl_node! {
 }
 
 impl_node! {
-    Assign, assign => value => {
-        let span = value.as_span();
-        let (ident, expr) = value.into_inner().next_tuple().expect("Assign should have ident and expr");
-        let ident = Ident::try_from(ident)?;
-        let expr = Expr::try_from(expr)?;
+    Assign, assign => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let mut inner = value.into_inner();
+        let ident = Ident::from_pair(inner.next().expect("Assign should have ident"), file)?;
+        let expr = Expr::from_pair(inner.next_back().expect("Assign should have expr"), file)?;
+        let indices = inner.map(|pair| postfix_index(pair, file)).try_collect()?;
         Assign {
             ident,
+            indices,
             expr,
             span,
         }
@@ -109,17 +142,55 @@ impl_node! {
 }
 
 impl_node! {
-    If, if_loop => value => {
-        let span = value.as_span();
+    EnvAssign, env_assign => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let (var, expr) = value.into_inner().next_tuple().expect("EnvAssign should have env var and expr");
+        let ident = env_var_ident(var, file)?;
+        let expr = Expr::from_pair(expr, file)?;
+        EnvAssign {
+            span,
+            ident,
+            expr,
+        }
+    }
+}
+
+impl_node! {
+    WithEnv, with_env => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let (vars, block) = value.into_inner().next_tuple().expect("WithEnv should have vars and body");
+        WithEnv {
+            span,
+            vars: Expr::from_pair(vars, file)?,
+            block: Block::from_pair(block, file)?,
+        }
+    }
+}
+
+impl_node! {
+    Within, within => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let (dir, block) = value.into_inner().next_tuple().expect("Within should have dir and body");
+        Within {
+            span,
+            dir: Expr::from_pair(dir, file)?,
+            block: Block::from_pair(block, file)?,
+        }
+    }
+}
+
+impl_node! {
+    If, if_loop => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
         let mut inner = value.into_inner();
         let (cond, body) = inner.next_tuple().expect("If should have cond and body");
-        let else_block = match  inner.next().map(Block::try_from) {
+        let else_block = match  inner.next().map(|pair| Block::from_pair(pair, file)) {
             Some(Ok(block)) => Some(block),
             Some(Err(err)) => return Err(err),
             None => None,
         };
-        let cond = Expr::try_from(cond)?;
-        let then_block = Block::try_from(body)?;
+        let cond = Expr::from_pair(cond, file)?;
+        let then_block = Block::from_pair(body, file)?;
         If {
             cond,
             then_block,
@@ -130,13 +201,13 @@ impl_node! {
 }
 
 impl_node! {
-    For, for_loop => value => {
-        let span = value.as_span();
+    For, for_loop => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
         let (ident, expr, block) = value
             .into_inner()
             .next_tuple()
             .map(|(a, b, c)| Result::Ok((
-                    TryFrom::try_from(a)?,TryFrom::try_from(b)?,TryFrom::try_from(c)?,
+                    FromPair::from_pair(a, file)?,FromPair::from_pair(b, file)?,FromPair::from_pair(c, file)?,
                 ))
             ).expect("For should have ident, expr and body")?;
 
@@ -150,13 +221,13 @@ impl_node! {
 }
 
 impl_node! {
-    While, while_loop => value => {
-        let span = value.as_span();
+    While, while_loop => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
         let ( expr, block) = value
             .into_inner()
             .next_tuple()
             .map(|(a, b)| Result::Ok((
-                    TryFrom::try_from(a)?,TryFrom::try_from(b)?,
+                    FromPair::from_pair(a, file)?,FromPair::from_pair(b, file)?,
                 ))
             ).expect("For should have ident, expr and body")?;
 
@@ -169,12 +240,45 @@ impl_node! {
 }
 
 impl_node! {
-    Stmt, stmt => value => {
-        let span = value.as_span();
+    Return, return_item => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let expr = value.into_inner().next().map(|pair| Expr::from_pair(pair, file)).transpose()?;
+        Return { span, expr }
+    }
+}
+
+impl_node! {
+    Break, break_item => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let expr = value.into_inner().next().map(|pair| Expr::from_pair(pair, file)).transpose()?;
+        Break { span, expr }
+    }
+}
+
+impl_node! {
+    Continue, continue_item => value, file => Continue {
+        span: Span::from_pest(&value.as_span(), file),
+    }
+}
+
+impl_node! {
+    Use, use_item => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let ident = value.into_inner().next().expect("Use should have ident");
+        Use {
+            ident: Ident::from_pair(ident, file)?,
+            span,
+        }
+    }
+}
+
+impl_node! {
+    Stmt, stmt => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
         let mut inner = value.into_inner();
         let (ident, expr) = inner.next_tuple().expect("Stmt should have ident and expr");
-        let ident = Ident::try_from(ident)?;
-        let expr = Expr::try_from(expr)?;
+        let ident = Ident::from_pair(ident, file)?;
+        let expr = Expr::from_pair(expr, file)?;
         Stmt {
             ident,
             expr,
@@ -184,18 +288,18 @@ impl_node! {
 }
 
 impl_node! {
-    FnDef, fn_def => value => {
-        let span = value.as_span();
+    FnDef, fn_def => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
         let mut inner = value.into_inner();
-        let ident = TryFrom::try_from(inner.next().expect("FnDef should have ident"))?;
+        let ident = FromPair::from_pair(inner.next().expect("FnDef should have ident"), file)?;
 
         let (params, body) = match inner.next().expect("FnDef should have ident or block")  {
             params if params.as_rule() == Rule::ident_list => {
-                let params = params.into_inner().map(Ident::try_from).collect::<Result<Vec<_>>>()?;
-                let body = Block::try_from(inner.next().expect("FnDef should have block"))?;
+                let params = params.into_inner().map(|pair| Ident::from_pair(pair, file)).collect::<Result<Vec<_>>>()?;
+                let body = Block::from_pair(inner.next().expect("FnDef should have block"), file)?;
                 (params, body)
             },
-            block => (vec![], Block::try_from(block)?)
+            block => (vec![], Block::from_pair(block, file)?)
         };
 
         FnDef { span, ident, params, body }
@@ -203,22 +307,21 @@ impl_node! {
 }
 
 impl_node! {
-    Ident, ident => value => Ident {
-        name: value.as_str(),
-        span: value.as_span(),
+    Ident, ident, path => value, file => Ident {
+        name: value.as_str().to_owned(),
+        span: Span::from_pest(&value.as_span(), file),
     }
 }
 
 impl_node! {
-    Literal, literal => value => {
-        let span = value.as_span();
-        let span1 = value.as_span();
+    Literal, literal => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
         let inner = value.into_inner().next().expect("Literal should have content");
         let kind = match inner.as_rule() {
             Rule::number => LiteralKind::Number(inner.as_str().parse().map_err(|_| {
                 Error::LiteralError {
                     expect: Rule::number,
-                    val: inner.as_str(),
+                    val: inner.as_str().to_owned(),
                     span,
                 }
             })?),
@@ -226,7 +329,7 @@ impl_node! {
                 LiteralKind::Float(inner.as_str().parse().map_err(|_| {
                 Error::LiteralError {
                     expect: Rule::float,
-                    val: inner.as_str(),
+                    val: inner.as_str().to_owned(),
                     span,
                 }
             })?)},
@@ -235,31 +338,93 @@ impl_node! {
                 "false" => false,
                 _ => unreachable!("Bool should have true or false"),
             }),
-            Rule::string => LiteralKind::String(&inner.as_str()[1..inner.as_str().len() - 1]),
+            Rule::string => LiteralKind::String(inner.as_str()[1..inner.as_str().len() - 1].to_owned()),
             _ => unreachable!("Literal should only be number, float or string"),
         };
         Literal {
         kind,
-        span: span1,
+        span,
     }}
 }
 
 impl_node! {
-    Exec, exec => value => Exec {
-        cmd: value.as_str().trim_start_matches("$`").trim_end_matches('`'),
-        span: value.as_span(),
+    Exec, exec => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let src = value.as_str();
+        let mut inner = value.into_inner().peekable();
+        let structured = inner.next_if(|pair| pair.as_rule() == Rule::exec_status).is_some();
+        let cmd = src
+            .trim_start_matches(if structured { "$?`" } else { "$`" })
+            .trim_end_matches('`')
+            .to_owned();
+        let parts = inner.map(|pair| TemplatePart::from_pair(pair, file)).try_collect()?;
+        Exec { span, cmd, parts, structured }
     }
 }
 
 impl_node! {
-    FnCall, fn_call => value => {
-        let span = value.as_span();
+    Template, string => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let parts = value.into_inner().map(|pair| TemplatePart::from_pair(pair, file)).try_collect()?;
+        Template { span, parts }
+    }
+}
+
+impl FromPair for TemplatePart {
+    fn from_pair(value: Pair<'_, Rule>, file: FileId) -> Result<Self> {
+        ensure!(
+            value in file,
+            str_text,
+            exec_text,
+            escaped_interp,
+            interp,
+            raw_interp
+        );
+        Ok(match value.as_rule() {
+            Rule::str_text | Rule::exec_text => TemplatePart::Text(value.as_str().to_owned()),
+            Rule::escaped_interp => TemplatePart::Text("${".to_owned()),
+            Rule::interp => TemplatePart::Interp(Expr::from_pair(
+                value
+                    .into_inner()
+                    .next()
+                    .expect("Interpolation should have expr"),
+                file,
+            )?),
+            _ => TemplatePart::RawInterp(Expr::from_pair(
+                value
+                    .into_inner()
+                    .next()
+                    .expect("Interpolation should have expr"),
+                file,
+            )?),
+        })
+    }
+}
+
+/// Whether a `literal` pair is a string containing interpolations or escaped
+/// interpolations.
+fn is_template(literal: &Pair<Rule>) -> bool {
+    literal
+        .clone()
+        .into_inner()
+        .next()
+        .filter(|inner| inner.as_rule() == Rule::string)
+        .is_some_and(|string| {
+            string
+                .into_inner()
+                .any(|part| part.as_rule() != Rule::str_text)
+        })
+}
+
+impl_node! {
+    FnCall, fn_call => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
         let mut inner = value.into_inner();
-        let ident = TryFrom::try_from(inner.next().expect("FnCall should have ident"))?;
+        let ident = FromPair::from_pair(inner.next().expect("FnCall should have ident"), file)?;
         let args = match inner.next()  {
             Some(args) => {
-                ensure!(args, expr_list);
-                args.into_inner().map(Expr::try_from).collect::<Result<Vec<_>>>()?
+                ensure!(args in file, expr_list);
+                args.into_inner().map(|pair| Expr::from_pair(pair, file)).collect::<Result<Vec<_>>>()?
             },
             None => vec![]
         };
@@ -273,28 +438,52 @@ impl_node! {
 }
 
 impl_node! {
-    Expr, expr, bin_op_expr, range, trivial_expr => value => {
-        let span = value.as_span();
+    Expr, expr, bin_op_expr, range, trivial_expr => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
         let kind = match value.as_rule() {
-            Rule::bin_op_expr => ExprKind::BinOp(BinOpExpr::try_from(value)?),
-            Rule::range => todo!(),
+            Rule::bin_op_expr => ExprKind::BinOp(BinOpExpr::from_pair(value, file)?),
+            Rule::range => ExprKind::Range(RangeExpr::from_pair(value, file)?),
             Rule::expr => {
                 value = value.into_inner().next().expect("Expr should have content");
-                return Expr::try_from(value)
+                return Expr::from_pair(value, file)
             }
             Rule::trivial_expr  => {
-                value = value.into_inner().next().expect("Expr should have content");
-                ensure!(value, literal, un_op_expr, fn_call, exec, block, unit, ident);
-                match value.as_rule() {
-                    Rule::literal => ExprKind::Literal(Literal::try_from(value)?),
-                    Rule::un_op_expr => ExprKind::UnOp(UnOpExpr::try_from(value)?),
-                    Rule::fn_call => ExprKind::FnCall(FnCall::try_from(value)?),
-                    Rule::exec => ExprKind::Exec(Exec::try_from(value)?),
-                    Rule::block => ExprKind::Block(Block::try_from(value)?),
+                let mut inner = value.into_inner();
+                value = inner.next().expect("Expr should have content");
+                ensure!(value in file, literal, un_op_expr, list, map, closure, fn_call, exec, env_var, block, unit, paren, path, ident);
+                let span = Span::from_pest(&value.as_span(), file);
+                let kind = match value.as_rule() {
+                    Rule::literal if is_template(&value) => ExprKind::Template(Template::from_pair(
+                        value.into_inner().next().expect("Literal should have content"), file)?),
+                    Rule::literal => ExprKind::Literal(Literal::from_pair(value, file)?),
+                    Rule::un_op_expr => ExprKind::UnOp(UnOpExpr::from_pair(value, file)?),
+                    Rule::list => ExprKind::List(ListExpr::from_pair(value, file)?),
+                    Rule::map => ExprKind::Map(MapExpr::from_pair(value, file)?),
+                    Rule::closure => ExprKind::Closure(ClosureExpr::from_pair(value, file)?),
+                    Rule::fn_call => ExprKind::FnCall(FnCall::from_pair(value, file)?),
+                    Rule::exec => ExprKind::Exec(Exec::from_pair(value, file)?),
+                    Rule::env_var => ExprKind::EnvVar(env_var_ident(value, file)?),
+                    Rule::block => ExprKind::Block(Block::from_pair(value, file)?),
                     Rule::unit => ExprKind::Unit,
-                    Rule::ident => ExprKind::Ident(Ident::try_from(value)?),
+                    Rule::paren => Expr::from_pair(value.into_inner().next().expect("Paren should have expr"), file)?.kind,
+                    Rule::path | Rule::ident => ExprKind::Ident(Ident::from_pair(value, file)?),
                     _ => unreachable!("Expr should only be bin_op_expr, range, trivial_expr"),
-                }
+                };
+
+                // Fold trailing `[index]`s and `.field`s from left to right, so `a[0].b` is
+                // `(a[0]).b`
+                return inner.try_fold(Expr { kind, span }, |expr, index| {
+                    let span = expr.span.to(Span::from_pest(&index.as_span(), file));
+                    let index = postfix_index(index, file)?;
+                    Ok(Expr {
+                        kind: ExprKind::Index(IndexExpr {
+                            expr: Box::new(expr),
+                            index: Box::new(index),
+                            span,
+                        }),
+                        span,
+                    })
+                });
             },
             rule => unreachable!("Expr should only be expr, trivial_expr, bin_op_expr or range, found {rule:?}"),
         };
@@ -306,45 +495,172 @@ impl_node! {
     }
 }
 
+/// Binary operators from the loosest to the tightest: `||`, `&&`, comparison,
+/// `+ -`, `* / %`, then `**`, which is the only right associative one.
+static PRATT: LazyLock<PrattParser<Rule>> = LazyLock::new(|| {
+    let left = |rule| Op::infix(rule, Assoc::Left);
+    PrattParser::new()
+        .op(left(Rule::or))
+        .op(left(Rule::and))
+        .op(left(Rule::eq)
+            | left(Rule::neq)
+            | left(Rule::lt)
+            | left(Rule::le)
+            | left(Rule::gt)
+            | left(Rule::ge))
+        .op(left(Rule::add) | left(Rule::sub))
+        .op(left(Rule::mul) | left(Rule::div) | left(Rule::rem))
+        .op(Op::infix(Rule::pow, Assoc::Right))
+});
+
 impl_node! {
-    BinOpExpr, bin_op_expr => value => {
-        let span = value.as_span();
-        let (left, op, right) = value.into_inner().next_tuple().expect("BinOpExpr should have (expr, op, expr)");
-        ensure!(op, bin_op);
-        let kind = {
-            match op.into_inner().next().expect("Bin op should have one child").as_rule() {
-                Rule::add => BinOpKind::Add,
-                Rule::sub => BinOpKind::Sub,
-                Rule::mul => BinOpKind::Mul,
-                Rule::div => BinOpKind::Div,
-                Rule::eq => BinOpKind::Eq,
-                Rule::neq => BinOpKind::Neq,
-                Rule::lt => BinOpKind::Lt,
-                Rule::le => BinOpKind::Le,
-                Rule::gt => BinOpKind::Gt,
-                Rule::ge => BinOpKind::Ge,
-                Rule::and => BinOpKind::And,
-                Rule::or => BinOpKind::Or,
-                _ => unreachable!(),
+    BinOpExpr, bin_op_expr => value, file => {
+        // The parser sees the operators themselves instead of `bin_op`s
+        let pairs = value.into_inner().map(|pair| match pair.as_rule() {
+            Rule::bin_op => pair.into_inner().next().expect("Bin op should have one child"),
+            _ => pair,
+        });
+        let expr = PRATT
+            .map_primary(|operand| Expr::from_pair(operand, file))
+            .map_infix(|left, op, right| {
+                let (left, right) = (left?, right?);
+                let span = left.span.to(right.span);
+                let kind = match op.as_rule() {
+                    Rule::add => BinOpKind::Add,
+                    Rule::sub => BinOpKind::Sub,
+                    Rule::mul => BinOpKind::Mul,
+                    Rule::div => BinOpKind::Div,
+                    Rule::rem => BinOpKind::Rem,
+                    Rule::pow => BinOpKind::Pow,
+                    Rule::eq => BinOpKind::Eq,
+                    Rule::neq => BinOpKind::Neq,
+                    Rule::lt => BinOpKind::Lt,
+                    Rule::le => BinOpKind::Le,
+                    Rule::gt => BinOpKind::Gt,
+                    Rule::ge => BinOpKind::Ge,
+                    Rule::and => BinOpKind::And,
+                    Rule::or => BinOpKind::Or,
+                    _ => unreachable!(),
+                };
+                Ok(Expr {
+                    kind: ExprKind::BinOp(BinOpExpr {
+                        left: Box::new(left),
+                        right: Box::new(right),
+                        kind,
+                        span,
+                    }),
+                    span,
+                })
+            })
+            .parse(pairs)?;
+        match expr.kind {
+            ExprKind::BinOp(bin_op) => bin_op,
+            _ => unreachable!("BinOpExpr should have at least one operator"),
+        }
+    }
+}
+
+impl_node! {
+    ListExpr, list => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let items = match value.into_inner().next() {
+            Some(items) => {
+                ensure!(items in file, expr_list);
+                items.into_inner().map(|pair| Expr::from_pair(pair, file)).try_collect()?
             }
+            None => vec![],
         };
-        let left = Box::new(Expr::try_from(left)?);
-        let right = Box::new(Expr::try_from(right)?);
-        BinOpExpr {
-            left,
-            kind,
-            right,
+        ListExpr { items, span }
+    }
+}
+
+impl_node! {
+    ClosureExpr, closure => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let mut inner = value.into_inner().peekable();
+        let params = match inner.next_if(|pair| pair.as_rule() == Rule::ident_list) {
+            Some(params) => params.into_inner().map(|pair| Ident::from_pair(pair, file)).try_collect()?,
+            None => vec![],
+        };
+        let body = inner.next().expect("Closure should have body");
+        let body = match body.as_rule() {
+            Rule::block => Expr {
+                span: Span::from_pest(&body.as_span(), file),
+                kind: ExprKind::Block(Block::from_pair(body, file)?),
+            },
+            _ => Expr::from_pair(body, file)?,
+        };
+        ClosureExpr {
+            params,
+            body: Box::new(body),
             span,
         }
     }
 }
 
 impl_node! {
-    UnOpExpr, un_op_expr => value => {
-        let span = value.as_span();
+    MapExpr, map => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let entries = value.into_inner().map(|pair| MapEntry::from_pair(pair, file)).try_collect()?;
+        MapExpr { entries, span }
+    }
+}
+
+impl_node! {
+    MapEntry, map_entry => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let (key, expr) = value.into_inner().next_tuple().expect("MapEntry should have key and expr");
+        let key = match key.as_rule() {
+            Rule::ident => key.as_str().to_owned(),
+            Rule::string => key.as_str()[1..key.as_str().len() - 1].to_owned(),
+            _ => unreachable!("MapEntry key should be ident or string"),
+        };
+        let expr = Expr::from_pair(expr, file)?;
+        MapEntry { key, expr, span }
+    }
+}
+
+/// Convert an `index` or `field` pair into the expression used as index.
+fn postfix_index(pair: Pair<'_, Rule>, file: FileId) -> Result<Expr> {
+    ensure!(pair in file, index, field);
+    let inner = pair.into_inner().next().expect("Index should have content");
+    match inner.as_rule() {
+        Rule::ident => Ok(Expr {
+            kind: ExprKind::Literal(Literal {
+                kind: LiteralKind::String(inner.as_str().to_owned()),
+                span: Span::from_pest(&inner.as_span(), file),
+            }),
+            span: Span::from_pest(&inner.as_span(), file),
+        }),
+        _ => Expr::from_pair(inner, file),
+    }
+}
+
+/// Name of an `env_var`, without the `$`.
+fn env_var_ident(pair: Pair<'_, Rule>, file: FileId) -> Result<Ident> {
+    ensure!(pair in file, env_var);
+    Ident::from_pair(
+        pair.into_inner().next().expect("Env var should have ident"),
+        file,
+    )
+}
+
+impl_node! {
+    RangeExpr, range => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let (start, end) = value.into_inner().next_tuple().expect("RangeExpr should have start and end");
+        let start = Box::new(Expr::from_pair(start, file)?);
+        let end = Box::new(Expr::from_pair(end, file)?);
+        RangeExpr { start, end, span }
+    }
+}
+
+impl_node! {
+    UnOpExpr, un_op_expr => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
         let (op, expr) = value.into_inner().next_tuple().expect("UnOpExpr should have op and expr");
-        let expr = Box::new(Expr::try_from(expr)?);
-        ensure!(op, un_op);
+        let expr = Box::new(Expr::from_pair(expr, file)?);
+        ensure!(op in file, un_op);
         let kind = {
             match op.into_inner().next().expect("Bin op should have one child").as_rule() {
                 Rule::not => UnOpKind::Not,
@@ -356,16 +672,25 @@ impl_node! {
     }
 }
 impl_node! {
-    Block, block => value => {
-        let span = value.as_span();
-        let items = value.into_inner().map(Item::try_from).try_collect()?;
+    Block, block => value, file => {
+        let span = Span::from_pest(&value.as_span(), file);
+        let mut items = vec![];
+        let mut expr = None;
+        for pair in value.into_inner() {
+            match pair.as_rule() {
+                Rule::item => items.push(Item::from_pair(pair, file)?),
+                Rule::expr => expr = Some(Box::new(Expr::from_pair(pair, file)?)),
+                _ => unreachable!("Block should only contain item and expr"),
+            }
+        }
         Block {
             span,
             items,
+            expr,
         }
     }
 }
-impl fmt::Display for FnDef<'_> {
+impl fmt::Display for FnDef {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "fn {}(", self.ident)?;
         for (i, ident) in self.params.iter().enumerate() {
@@ -378,7 +703,7 @@ impl fmt::Display for FnDef<'_> {
     }
 }
 
-impl Hash for LiteralKind<'_> {
+impl Hash for LiteralKind {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         match self {
             LiteralKind::String(s) => s.hash(state),
@@ -389,19 +714,21 @@ impl Hash for LiteralKind<'_> {
     }
 }
 
-impl fmt::Display for Ident<'_> {
+impl fmt::Display for Ident {
     fn fmt(&self, f: &mut fmt::Formatter) -> std::fmt::Result {
         write!(f, "Ident({})", self.name)
     }
 }
 
-impl BinOpKind<'_> {
+impl BinOpKind {
     pub fn as_str(&self) -> &'static str {
         match self {
             BinOpKind::Add => "+",
             BinOpKind::Sub => "-",
             BinOpKind::Mul => "*",
             BinOpKind::Div => "/",
+            BinOpKind::Rem => "%",
+            BinOpKind::Pow => "**",
             BinOpKind::Eq => "==",
             BinOpKind::Neq => "!=",
             BinOpKind::Lt => "<",
@@ -410,7 +737,6 @@ impl BinOpKind<'_> {
             BinOpKind::Ge => ">=",
             BinOpKind::And => "&&",
             BinOpKind::Or => "||",
-            BinOpKind::__Marker(_) => unreachable!(),
         }
     }
 }
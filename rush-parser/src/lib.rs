@@ -1,30 +1,94 @@
-#![cfg_attr(test, feature(assert_matches))]
-
 use pest::Parser;
 pub mod ast;
+mod diagnostic;
 mod error;
 mod impl_ast;
+mod recover;
+mod scan;
+mod source;
 
 #[cfg(test)]
 mod test;
 
+pub use diagnostic::*;
 pub use error::*;
+pub use impl_ast::Node;
+pub use scan::*;
+pub use source::*;
 
-use crate::ast::Tree;
+use crate::{ast::Tree, impl_ast::FromPair};
 
 #[derive(pest_derive::Parser)]
 #[grammar = "../../pest/rush.pest"]
 pub struct RushParser;
 
-pub fn parse<'src>(input: &'src str) -> Result<'src, Tree<'src>> {
-    let res = RushParser::parse(Rule::main, input)?.next().unwrap();
-    let tree = Tree::try_from(res)?;
+/// Parse `input` as a standalone source, with spans in the default file.
+pub fn parse(input: &str) -> Result<Tree> {
+    parse_file(input, FileId::default())
+}
+
+/// Parse `input`, which is the source of `file`, into an owned tree. Stops at
+/// the first error, see [`parse_recovering`] to find all of them.
+pub fn parse_file(input: &str, file: FileId) -> Result<Tree> {
+    let res = RushParser::parse(Rule::main, input)
+        .map_err(|err| Error::from_pest(&err, input, file))?
+        .next()
+        .unwrap();
+    let tree = Tree::from_pair(res, file)?;
 
     Ok(tree)
 }
 
+/// A tree parsed with error recovery.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parsed {
+    /// Every item that could be parsed, items with errors are left out
+    pub tree: Tree,
+    /// Errors in the order they appear in the source
+    pub errors: Vec<Error>,
+}
+
+impl Parsed {
+    /// The tree if there was no error.
+    pub fn into_result(self) -> std::result::Result<Tree, Vec<Error>> {
+        if self.errors.is_empty() {
+            Ok(self.tree)
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+/// Parse `input`, which is the source of `file`, going on after syntax errors
+/// by skipping the items they are in, up to the next `;` or `}`.
+#[must_use]
+pub fn parse_recovering(input: &str, file: FileId) -> Parsed {
+    let mut src = input.to_owned();
+    let mut errors = vec![];
+    let tree = loop {
+        let err = match RushParser::parse(Rule::main, &src) {
+            Ok(mut pairs) => {
+                let main = pairs.next().unwrap();
+                break Tree::from_pair_lossy(main, file, &mut errors);
+            }
+            Err(err) => Error::from_pest(&err, &src, file),
+        };
+        let pos = err.span().map_or(src.len(), |span| span.start);
+        errors.push(err);
+        if !recover::skip_item(&mut src, pos) && !recover::skip_rest(&mut src, pos) {
+            break Tree {
+                span: Span::new(file, 0, input.len()),
+                items: vec![],
+                expr: None,
+            };
+        }
+    };
+    errors.sort_by_key(|err| err.span().map(|span| span.start));
+    Parsed { tree, errors }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Spanned<'str, T> {
-    pub span: pest::Span<'str>,
+pub struct Spanned<T> {
+    pub span: Span,
     pub value: T,
 }
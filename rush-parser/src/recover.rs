@@ -0,0 +1,70 @@
+//! Skipping broken items, so that parsing can go on after a syntax error.
+//!
+//! Skipped text is replaced by spaces instead of being cut out, which keeps
+//! the spans of everything after it right. Item boundaries are found by
+//! looking at `;`, `{` and `}` outside of strings, commands and comments only,
+//! so a skip may be larger or smaller than the item, but never stops the
+//! parsing from going on.
+
+use std::ops::Range;
+
+use crate::CodeChars;
+
+/// Skip the item around the error at `pos`: from the last `;`, `{` or `}`
+/// before it, to the next `;` (included) or `}` (left for the enclosing
+/// block). `false` if there was nothing to skip.
+pub(crate) fn skip_item(src: &mut String, pos: usize) -> bool {
+    let start = CodeChars::new(src)
+        .take_while(|(i, _)| *i < pos)
+        .filter(|(_, c)| matches!(c, ';' | '{' | '}'))
+        .last()
+        .map_or(0, |(i, _)| i + 1);
+    let end = match CodeChars::new(src).find(|(i, c)| *i >= pos && matches!(c, ';' | '}')) {
+        Some((i, ';')) => i + 1,
+        Some((i, _)) => i,
+        None => src.len(),
+    };
+    blank(src, start..end)
+}
+
+/// Skip everything from the start of the top level item around `pos`, for
+/// errors there is no item to skip for, like an unclosed block. `false` if
+/// there was nothing to skip.
+pub(crate) fn skip_rest(src: &mut String, pos: usize) -> bool {
+    let mut depth = 0_usize;
+    let mut start = 0;
+    for (i, c) in CodeChars::new(src).take_while(|(i, _)| *i < pos) {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        if depth == 0 && matches!(c, ';' | '}') {
+            start = i + 1;
+        }
+    }
+    let end = src.len();
+    blank(src, start..end)
+}
+
+/// Replace `range` with spaces, keeping newlines and byte offsets. Whether
+/// anything but whitespace was replaced.
+fn blank(src: &mut String, range: Range<usize>) -> bool {
+    let text = &src[range.clone()];
+    if text.trim().is_empty() {
+        return false;
+    }
+    let blanked: String = text
+        .chars()
+        .flat_map(|c| {
+            let (fill, len) = if c == '\n' {
+                ('\n', 1)
+            } else {
+                (' ', c.len_utf8())
+            };
+            std::iter::repeat_n(fill, len)
+        })
+        .collect();
+    src.replace_range(range, &blanked);
+    true
+}
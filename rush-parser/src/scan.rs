@@ -0,0 +1,87 @@
+use std::{iter::Peekable, str::CharIndices};
+
+/// Chars of rush source that are code, with their byte offsets: string
+/// literals, commands and comments are skipped, along with their quotes and
+/// the interpolations in them. Good enough to match brackets or find the end
+/// of an item without parsing.
+#[derive(Debug, Clone)]
+pub struct CodeChars<'a> {
+    src: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    /// What the chars are in, innermost last, nothing for top level code
+    nesting: Vec<Nesting>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Nesting {
+    Str,
+    Command,
+    /// `${..}` in a string or command, with the number of its own brackets
+    /// left open
+    Interp(usize),
+}
+
+impl<'a> CodeChars<'a> {
+    #[must_use]
+    pub fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            chars: src.char_indices().peekable(),
+            nesting: vec![],
+        }
+    }
+
+    /// Whether the source ends inside of a string, command or interpolation,
+    /// which may go on over many lines.
+    #[must_use]
+    pub fn unclosed(&self) -> bool {
+        !self.nesting.is_empty()
+    }
+
+    /// Enter the interpolation starting with the `$` at `i`, if any. `$${` is
+    /// a plain `${`.
+    fn interp_start(&mut self, i: usize) {
+        let rest = &self.src[i..];
+        if rest.starts_with("${") {
+            self.chars.next();
+            self.nesting.push(Nesting::Interp(0));
+        } else if rest.starts_with("$!{") {
+            self.chars.nth(1);
+            self.nesting.push(Nesting::Interp(0));
+        } else if rest.starts_with("$${") {
+            self.chars.nth(1);
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        self.chars.by_ref().find(|(_, c)| *c == '\n');
+    }
+}
+
+impl Iterator for CodeChars<'_> {
+    type Item = (usize, char);
+
+    fn next(&mut self) -> Option<(usize, char)> {
+        loop {
+            let (i, c) = self.chars.next()?;
+            let is_comment = c == '/' && matches!(self.chars.peek(), Some((_, '/')));
+            match (self.nesting.last_mut(), c) {
+                (None | Some(Nesting::Interp(_)), '"') => self.nesting.push(Nesting::Str),
+                (None | Some(Nesting::Interp(_)), '`') => self.nesting.push(Nesting::Command),
+                (None | Some(Nesting::Interp(_)), '/') if is_comment => self.skip_comment(),
+                (None, c) => return Some((i, c)),
+                (Some(Nesting::Interp(depth)), '{') => *depth += 1,
+                (Some(Nesting::Interp(0)), '}') => {
+                    self.nesting.pop();
+                }
+                (Some(Nesting::Interp(depth)), '}') => *depth -= 1,
+                (Some(Nesting::Str), '\\') => drop(self.chars.next()),
+                (Some(Nesting::Str), '"') | (Some(Nesting::Command), '`') => {
+                    self.nesting.pop();
+                }
+                (Some(Nesting::Str | Nesting::Command), '$') => self.interp_start(i),
+                _ => {}
+            }
+        }
+    }
+}
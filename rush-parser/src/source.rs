@@ -0,0 +1,121 @@
+use std::{fmt, ops::Range};
+
+/// Identifies a source file in a [`SourceMap`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId(pub u32);
+
+/// Byte range of a node in its source file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    #[must_use]
+    pub const fn new(file: FileId, start: usize, end: usize) -> Self {
+        Self { file, start, end }
+    }
+
+    pub(crate) fn from_pest(span: &pest::Span<'_>, file: FileId) -> Self {
+        Self::new(file, span.start(), span.end())
+    }
+
+    /// Span from the start of `self` to the end of `other`.
+    #[must_use]
+    pub const fn to(self, other: Self) -> Self {
+        Self::new(self.file, self.start, other.end)
+    }
+
+    #[must_use]
+    pub const fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    /// Text of the span in `src`, which is the source it was parsed from.
+    #[must_use]
+    pub fn as_str<'a>(&self, src: &'a str) -> &'a str {
+        &src[self.range()]
+    }
+}
+
+/// A source file with its name, e.g. a path or `<repl>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub name: String,
+    pub src: String,
+}
+
+impl SourceFile {
+    /// Line and column of the byte `offset`, both starting from 1. Columns
+    /// count chars, not bytes.
+    #[must_use]
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let before = &self.src[..offset.min(self.src.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let col = before[line_start..].chars().count() + 1;
+        (line, col)
+    }
+
+    /// The whole line `line` (starting from 1), without the newline.
+    #[must_use]
+    pub fn line(&self, line: usize) -> &str {
+        self.src.lines().nth(line - 1).unwrap_or_default()
+    }
+}
+
+/// All source files of a program, so that spans can be resolved to a file name,
+/// line and column.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, src: impl Into<String>) -> FileId {
+        let id = FileId(u32::try_from(self.files.len()).expect("Too many source files"));
+        self.files.push(SourceFile {
+            name: name.into(),
+            src: src.into(),
+        });
+        id
+    }
+
+    #[must_use]
+    pub fn get(&self, file: FileId) -> Option<&SourceFile> {
+        self.files.get(file.0 as usize)
+    }
+
+    /// `name:line:col` of the start of `span`.
+    #[must_use]
+    pub fn location(&self, span: Span) -> Location<'_> {
+        let file = self.get(span.file);
+        let (line, col) = file.map_or((0, 0), |file| file.line_col(span.start));
+        Location {
+            name: file.map_or("<unknown>", |file| file.name.as_str()),
+            line,
+            col,
+        }
+    }
+}
+
+/// Human readable position in a source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location<'a> {
+    pub name: &'a str,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.name, self.line, self.col)
+    }
+}
@@ -1,9 +1,12 @@
-use std::assert_matches::assert_matches;
+use std::assert_matches;
 
 use itertools::Itertools;
 use pest::Parser;
 
-use crate::{ast::*, parse, Rule, RushParser};
+use crate::{
+    ast::*, impl_ast::FromPair, parse, parse_file, parse_recovering, CodeChars, Diagnostic, Error,
+    FileId, Rule, RushParser, SourceMap,
+};
 
 macro_rules! assert_parse {
     ($src:expr, $rule:ident, $( $pattern:pat_param )+ $(|)? $( if $guard:expr )?) => {
@@ -11,7 +14,7 @@ macro_rules! assert_parse {
             .unwrap()
             .next()
             .unwrap();
-        let res = TryFrom::try_from(res).unwrap();
+        let res = FromPair::from_pair(res, FileId::default()).unwrap();
         assert_matches!(res, $($pattern)+ $( if $guard )? );
     };
 }
@@ -34,16 +37,16 @@ fn test_parse() {
     assert_matches!(
         fn_def.kind,
         ItemKind::FnDef(FnDef {
-            ident: Ident { name: "main", .. },
+            ident: Ident { name, .. },
             params,
             ..
-        }) if params.len() == 1
+        }) if name == "main" && params.len() == 1
     );
 
     assert_matches!(
         stmt1.kind,
         ItemKind::Stmt(Stmt {
-            ident: Ident { name: "a", .. },
+            ident: Ident { name, .. },
             expr: Expr {
                 kind: ExprKind::BinOp(BinOpExpr {
                     left,
@@ -54,32 +57,47 @@ fn test_parse() {
                 ..
             },
             ..
-        }) if matches!(&*left, Expr {
+        }) if name == "a" && matches!(&*left, Expr {
             kind: ExprKind::Literal(Literal {
                 kind: LiteralKind::Number(233),
                 ..
             }),
             ..
         }) && matches!(&*right, Expr {
-            kind: ExprKind::Ident(Ident { name: "c", .. }),
+            kind: ExprKind::Ident(Ident { name, .. }),
             ..
-        })
+        } if name == "c")
     );
 
     assert_matches!(
         stmt2.kind,
         ItemKind::Stmt(Stmt {
-            ident: Ident { name: "main", .. },
+            ident: Ident { name, .. },
             expr: Expr {
                 kind: ExprKind::Literal(Literal {
-                    kind: LiteralKind::String("mian"),
+                    kind: LiteralKind::String(s),
                     ..
                 }),
                 ..
             },
             ..
+        }) if name == "main" && s == "mian"
+    );
+}
+
+#[test]
+fn test_tree_value() {
+    let tree = parse("let a = 1; a + 1").unwrap();
+    assert!(tree.items.len() == 1);
+    assert_matches!(
+        tree.expr.as_deref(),
+        Some(Expr {
+            kind: ExprKind::BinOp(_),
+            ..
         })
     );
+
+    assert!(parse("let a = 1;").unwrap().expr.is_none());
 }
 
 #[test]
@@ -90,12 +108,12 @@ fn test_fn_def() {
         item,
         Item {
             kind: ItemKind::FnDef(FnDef {
-                ident: Ident { name: "test", .. },
+                ident: Ident { name, .. },
                 params,
                 ..
             }),
             ..
-        } | if params.len() == 2
+        } | if name == "test" && params.len() == 2
     );
 }
 
@@ -125,7 +143,79 @@ fn test_literal() {
 
 #[test]
 fn test_exec() {
-    assert_parse!("$`ls -al`", exec, Exec { cmd: "ls -al", .. });
+    assert_parse!(
+        "$`ls -al`",
+        exec,
+        Exec {
+            cmd,
+            structured: false,
+            ..
+        } | if cmd == "ls -al"
+    );
+    assert_parse!(
+        "$?`false`",
+        exec,
+        Exec {
+            cmd,
+            structured: true,
+            ..
+        } | if cmd == "false"
+    );
+}
+
+#[test]
+fn test_env() {
+    assert_parse!(
+        "$HOME",
+        expr,
+        Expr {
+            kind: ExprKind::EnvVar(Ident { name, .. }),
+            ..
+        } | if name == "HOME"
+    );
+    assert_parse!(
+        "$PATH = ();",
+        env_assign,
+        EnvAssign {
+            ident: Ident { name, .. },
+            expr: Expr {
+                kind: ExprKind::Unit,
+                ..
+            },
+            ..
+        } | if name == "PATH"
+    );
+    assert_parse!(
+        "with_env #{ LANG: \"C\" } { $`locale`; }",
+        with_env,
+        WithEnv {
+            vars: Expr {
+                kind: ExprKind::Map(_),
+                ..
+            },
+            block: Block { items, .. },
+            ..
+        } | if items.len() == 1
+    );
+}
+
+#[test]
+fn test_within() {
+    assert_parse!(
+        "within \"/tmp\" { $`ls`; }",
+        within,
+        Within {
+            dir: Expr {
+                kind: ExprKind::Literal(Literal {
+                    kind: LiteralKind::String(dir),
+                    ..
+                }),
+                ..
+            },
+            block: Block { items, .. },
+            ..
+        } | if dir == "/tmp" && items.len() == 1
+    );
 }
 
 #[test]
@@ -138,9 +228,9 @@ fn test_un_op() {
             expr,
             ..
         } | if matches!(&*expr, Expr {
-            kind: ExprKind::Ident(Ident { name: "a", .. }),
+            kind: ExprKind::Ident(Ident { name, .. }),
             ..
-        }
+        } if name == "a"
     ));
 }
 
@@ -155,13 +245,81 @@ fn test_bin_op() {
             right,
             ..
         } | if matches!(&*left, Expr {
-            kind: ExprKind::Ident(Ident { name: "a", .. }),
+            kind: ExprKind::Ident(Ident { name, .. }),
             ..
-        }) && matches!(&*right, Expr {
-            kind: ExprKind::Ident(Ident { name: "b", .. }),
+        } if name == "a") && matches!(&*right, Expr {
+            kind: ExprKind::Ident(Ident { name, .. }),
             ..
-        })
+        } if name == "b")
+    };
+}
+
+/// `expr` with every binary and unary operation parenthesised, to check how
+/// operators group.
+fn grouped(expr: &Expr) -> String {
+    match &expr.kind {
+        ExprKind::BinOp(BinOpExpr {
+            left, right, kind, ..
+        }) => {
+            format!("({} {} {})", grouped(left), kind.as_str(), grouped(right))
+        }
+        ExprKind::UnOp(UnOpExpr { expr, kind, .. }) => {
+            let op = match kind {
+                UnOpKind::Neg => "-",
+                UnOpKind::Not => "!",
+            };
+            format!("({op}{})", grouped(expr))
+        }
+        ExprKind::Ident(Ident { name, .. }) => name.clone(),
+        ExprKind::Literal(Literal {
+            kind: LiteralKind::Number(i),
+            ..
+        }) => i.to_string(),
+        kind => panic!("Unexpected expression {kind:?}"),
+    }
+}
+
+#[test]
+fn test_precedence() {
+    let cases = [
+        ("1 - 2 - 3", "((1 - 2) - 3)"),
+        ("a / b * c", "((a / b) * c)"),
+        ("a % b * c", "((a % b) * c)"),
+        ("a * b ** c ** d", "(a * (b ** (c ** d)))"),
+        ("-a ** b", "((-a) ** b)"),
+        ("a * b + c", "((a * b) + c)"),
+        ("a + b * c", "(a + (b * c))"),
+        ("a + b < c * d", "((a + b) < (c * d))"),
+        ("a < b && c || d", "(((a < b) && c) || d)"),
+        ("a || b && c == d", "(a || (b && (c == d)))"),
+        ("(a + b) * c", "((a + b) * c)"),
+        ("a - (b - c)", "(a - (b - c))"),
+        ("-a + b", "((-a) + b)"),
+        ("!a && b", "((!a) && b)"),
+        ("((a))", "a"),
+    ];
+    for (src, expected) in cases {
+        let pair = RushParser::parse(Rule::expr, src).unwrap().next().unwrap();
+        let expr = Expr::from_pair(pair, FileId::default()).unwrap();
+        assert_eq!(grouped(&expr), expected, "{src}");
+    }
+}
+
+#[test]
+fn test_precedence_span() {
+    let tree = parse("x = 1 + 2 * 3;").unwrap();
+    let [Item {
+        kind: ItemKind::Assign(Assign { expr, .. }),
+        ..
+    }] = &*tree.items
+    else {
+        panic!("Expected one assignment, got {tree:?}");
+    };
+    let ExprKind::BinOp(BinOpExpr { right, .. }) = &expr.kind else {
+        panic!("Expected a binary operation, got {expr:?}");
     };
+    assert_eq!((expr.span.start, expr.span.end), (4, 13));
+    assert_eq!((right.span.start, right.span.end), (8, 13));
 }
 
 #[test]
@@ -179,14 +337,14 @@ fn test_loop() {
             },
             block: Block { items, .. },
             ..
-        } | if matches!(*items, [
+        } | if matches!(&*items, [
             Item {
                 kind: ItemKind::Expr(
                     Expr {
                         kind: ExprKind::FnCall(
                             FnCall {
                                 ident: Ident {
-                                    name: "print",
+                                    name,
                                     ..
                                 },
                                 ..
@@ -197,7 +355,7 @@ fn test_loop() {
                 ..
             ),
             ..
-        }])
+        }] if name == "print")
     );
 }
 
@@ -208,6 +366,449 @@ fn test_some() {
         .next()
         .unwrap();
 
-    let res = FnDef::try_from(res).unwrap();
+    let res = FnDef::from_pair(res, FileId::default()).unwrap();
     println!("{:#?}", res);
 }
+
+#[test]
+fn test_range() {
+    assert_parse!(
+        "0..n - 1",
+        expr,
+        Expr {
+            kind: ExprKind::Range(RangeExpr { start, end, .. }),
+            ..
+        } | if matches!(&*start, Expr {
+            kind: ExprKind::Literal(Literal {
+                kind: LiteralKind::Number(0),
+                ..
+            }),
+            ..
+        }) && matches!(&*end, Expr {
+            kind: ExprKind::BinOp(BinOpExpr {
+                kind: BinOpKind::Sub,
+                ..
+            }),
+            ..
+        })
+    );
+
+    assert_parse!(
+        "for i in 0..10 { print(i); }",
+        for_loop,
+        For {
+            ident: Ident { name, .. },
+            expr: Expr {
+                kind: ExprKind::Range(_),
+                ..
+            },
+            block: Block { items, .. },
+            ..
+        } | if name == "i" && items.len() == 1
+    );
+}
+
+#[test]
+fn test_block_value() {
+    assert_parse!(
+        "fn double(x) { let y = x; y * 2 }",
+        fn_def,
+        FnDef {
+            body: Block { items, expr: Some(expr), .. },
+            ..
+        } | if items.len() == 1 && matches!(&*expr, Expr {
+            kind: ExprKind::BinOp(BinOpExpr {
+                kind: BinOpKind::Mul,
+                ..
+            }),
+            ..
+        })
+    );
+
+    assert_parse!(
+        "{ print(a); }",
+        block,
+        Block {
+            items,
+            expr: None,
+            ..
+        } | if items.len() == 1
+    );
+
+    assert_parse!(
+        "return a + 1;",
+        return_item,
+        Return {
+            expr: Some(Expr {
+                kind: ExprKind::BinOp(_),
+                ..
+            }),
+            ..
+        }
+    );
+
+    assert_parse!("return;", return_item, Return { expr: None, .. });
+    assert_parse!("return(a);", return_item, Return { expr: Some(_), .. });
+
+    let tree = parse("return_value(1); returned;").unwrap();
+    assert_matches!(&*tree.items, [
+        Item { kind: ItemKind::Expr(Expr { kind: ExprKind::FnCall(FnCall { ident, .. }), .. }), .. },
+        Item { kind: ItemKind::Expr(Expr { kind: ExprKind::Ident(Ident { name, .. }), .. }), .. },
+    ] if ident.name == "return_value" && name == "returned");
+}
+
+#[test]
+fn test_loop_control() {
+    assert_parse!(
+        "while true { if a { break a; } continue; }",
+        while_loop,
+        While {
+            block: Block { items, .. },
+            ..
+        } | if matches!(&*items, [
+            Item {
+                kind: ItemKind::If(If { then_block: Block { items, .. }, .. }),
+                ..
+            },
+            Item {
+                kind: ItemKind::Continue(_),
+                ..
+            },
+        ] if matches!(&**items, [Item { kind: ItemKind::Break(Break { expr: Some(_), .. }), .. }]))
+    );
+
+    assert_parse!("break;", break_item, Break { expr: None, .. });
+
+    let tree = parse("breaker; continued;").unwrap();
+    assert_matches!(&*tree.items, [
+        Item { kind: ItemKind::Expr(Expr { kind: ExprKind::Ident(first), .. }), .. },
+        Item { kind: ItemKind::Expr(Expr { kind: ExprKind::Ident(second), .. }), .. },
+    ] if first.name == "breaker" && second.name == "continued");
+}
+
+#[test]
+fn test_use() {
+    assert_parse!(
+        "use foo;",
+        use_item,
+        Use { ident: Ident { name, .. }, .. } | if name == "foo"
+    );
+    assert_parse!(
+        "foo::bar(foo::baz)",
+        fn_call,
+        FnCall { ident: Ident { name, .. }, args, .. } | if name == "foo::bar" && matches!(&*args, [Expr {
+            kind: ExprKind::Ident(Ident { name, .. }),
+            ..
+        }] if name == "foo::baz")
+    );
+
+    let tree = parse(
+        "use foo;
+user;",
+    )
+    .unwrap();
+    assert_matches!(&*tree.items, [
+        Item { kind: ItemKind::Use(_), .. },
+        Item { kind: ItemKind::Expr(Expr { kind: ExprKind::Ident(Ident { name, .. }), .. }), .. },
+    ] if name == "user");
+    assert!(parse("use foo::bar;").is_err());
+}
+
+#[test]
+fn test_list() {
+    assert_parse!(
+        "[1, a, [],]",
+        expr,
+        Expr {
+            kind: ExprKind::List(ListExpr { items, .. }),
+            ..
+        } | if matches!(&*items, [
+            Expr { kind: ExprKind::Literal(_), .. },
+            Expr { kind: ExprKind::Ident(_), .. },
+            Expr { kind: ExprKind::List(ListExpr { items, .. }), .. },
+        ] if items.is_empty())
+    );
+
+    assert_parse!(
+        "xs[0][i]",
+        expr,
+        Expr {
+            kind: ExprKind::Index(IndexExpr { expr, index, .. }),
+            ..
+        } | if matches!(&*index, Expr {
+            kind: ExprKind::Ident(Ident { name, .. }),
+            ..
+        } if name == "i") && matches!(&*expr, Expr {
+            kind: ExprKind::Index(IndexExpr { expr, .. }),
+            span,
+        } if span.as_str("xs[0][i]") == "xs[0]" && matches!(&**expr, Expr {
+            kind: ExprKind::Ident(Ident { name, .. }),
+            ..
+        } if name == "xs"))
+    );
+
+    assert_parse!(
+        "xs[1] = 2;",
+        assign,
+        Assign {
+            ident: Ident { name, .. },
+            indices,
+            ..
+        } | if name == "xs" && indices.len() == 1
+    );
+}
+
+#[test]
+fn test_map() {
+    assert_parse!(
+        r#"#{ a: 1, "b c": [], }"#,
+        expr,
+        Expr {
+            kind: ExprKind::Map(MapExpr { entries, .. }),
+            ..
+        } | if matches!(&*entries, [
+            MapEntry { key: a, .. },
+            MapEntry { key: b, expr: Expr { kind: ExprKind::List(_), .. }, .. },
+        ] if a == "a" && b == "b c")
+    );
+
+    assert_parse!(
+        "m.a",
+        expr,
+        Expr {
+            kind: ExprKind::Index(IndexExpr { index, .. }),
+            ..
+        } | if matches!(&*index, Expr {
+            kind: ExprKind::Literal(Literal {
+                kind: LiteralKind::String(field),
+                ..
+            }),
+            ..
+        } if field == "a")
+    );
+
+    assert_parse!(
+        "m.a[0] = 1;",
+        assign,
+        Assign { indices, .. } | if indices.len() == 2
+    );
+}
+
+#[test]
+fn test_closure() {
+    assert_parse!(
+        "|x, y| x + y",
+        expr,
+        Expr {
+            kind: ExprKind::Closure(ClosureExpr { params, body, .. }),
+            ..
+        } | if params.len() == 2 && matches!(&*body, Expr {
+            kind: ExprKind::BinOp(_),
+            ..
+        })
+    );
+
+    assert_parse!(
+        "fn(x) { x }",
+        expr,
+        Expr {
+            kind: ExprKind::Closure(ClosureExpr { params, body, .. }),
+            ..
+        } | if params.len() == 1 && matches!(&*body, Expr {
+            kind: ExprKind::Block(_),
+            ..
+        })
+    );
+
+    assert_parse!(
+        "each(xs, || 1)",
+        fn_call,
+        FnCall { args, .. } | if matches!(&*args, [
+            Expr { kind: ExprKind::Ident(_), .. },
+            Expr { kind: ExprKind::Closure(ClosureExpr { params, .. }), .. },
+        ] if params.is_empty())
+    );
+}
+
+#[test]
+fn test_interpolation() {
+    assert_parse!(
+        "$`ls ${dir} | grep $!{pat} $${HOME}`",
+        exec,
+        Exec { cmd, parts, .. } | if cmd == "ls ${dir} | grep $!{pat} $${HOME}" && matches!(&*parts, [
+            TemplatePart::Text(t1),
+            TemplatePart::Interp(Expr { kind: ExprKind::Ident(dir), .. }),
+            TemplatePart::Text(t2),
+            TemplatePart::RawInterp(Expr { kind: ExprKind::Ident(pat), .. }),
+            TemplatePart::Text(t3),
+            TemplatePart::Text(t4),
+            TemplatePart::Text(t5),
+        ] if [t1, t2, t3, t4, t5] == ["ls ", " | grep ", " ", "${", "HOME}"]
+            && dir.name == "dir"
+            && pat.name == "pat")
+    );
+
+    assert_parse!(
+        r#""n = ${ n + 1 }!""#,
+        expr,
+        Expr {
+            kind: ExprKind::Template(Template { parts, .. }),
+            ..
+        } | if matches!(&*parts, [
+            TemplatePart::Text(t1),
+            TemplatePart::Interp(Expr { kind: ExprKind::BinOp(_), .. }),
+            TemplatePart::Text(t2),
+        ] if [t1, t2] == ["n = ", "!"])
+    );
+
+    assert_parse!(
+        r#""a $${b}""#,
+        expr,
+        Expr {
+            kind: ExprKind::Template(Template { parts, .. }),
+            ..
+        } | if matches!(&*parts, [
+            TemplatePart::Text(t1),
+            TemplatePart::Text(t2),
+            TemplatePart::Text(t3),
+        ] if [t1, t2, t3] == ["a ", "${", "b}"])
+    );
+}
+
+#[test]
+fn test_diagnostic() {
+    let mut sources = SourceMap::new();
+    sources.add("first.rush", "");
+    let src = "let a = 1;\nlet b = a +;\n";
+    let file = sources.add("second.rush", src);
+
+    let err = parse_file(src, file).unwrap_err();
+    let span = err.span().unwrap();
+    assert_eq!(span.file, file);
+    assert_eq!(sources.location(span).to_string(), "second.rush:2:12");
+
+    let rendered = err.diagnostic(&sources).to_string();
+    assert!(rendered.starts_with("error: Syntax error: expected an expression, found `;`\n"));
+    assert!(rendered.ends_with(
+        " --> second.rush:2:12
+  |
+2 | let b = a +;
+  |            ^"
+    ));
+
+    // Punctuation is named, not only the rules around it
+    for (src, msg) in [
+        ("fn f( { }", "expected `)` or an identifier, found `{`"),
+        ("fn f(a b) {}", "expected `,` or `)`, found `b`"),
+        ("#{ a 1 }", "expected `:`, found `1`"),
+        ("fn f x", "expected `(`, found `x`"),
+        ("x.1;", "expected an identifier, found `1`"),
+    ] {
+        assert_eq!(
+            parse(src).unwrap_err().to_string(),
+            format!("Syntax error: {msg}"),
+            "{src}"
+        );
+    }
+
+    let diagnostic = Diagnostic::new(&sources, "Oops", None);
+    assert_eq!(diagnostic.to_string(), "error: Oops");
+}
+
+#[test]
+fn test_recovery() {
+    let src = r#"
+        let a = 1;
+        let b = a +;
+        fn f(x) {
+            let y = x * ;
+            y
+        }
+        print(a b);
+        let n = 99999999999999999999;
+        a
+    "#;
+    let parsed = parse_recovering(src, FileId::default());
+
+    let lines = parsed
+        .errors
+        .iter()
+        .map(|err| src[..err.span().unwrap().start].lines().count())
+        .collect_vec();
+    assert_eq!(lines, [3, 5, 8, 9]);
+    assert_matches!(
+        &parsed.errors[0],
+        Error::Syntax { expected, found, .. } if expected == &[Rule::trivial_expr] && found == "`;`"
+    );
+    assert_eq!(
+        parsed.errors[3].to_string(),
+        "Invalid literal `99999999999999999999`, expected a number"
+    );
+
+    // `let a`, and `fn f` without its broken statement
+    assert_matches!(
+        &*parsed.tree.items,
+        [
+            Item { kind: ItemKind::Stmt(_), .. },
+            Item { kind: ItemKind::FnDef(FnDef { body: Block { items, expr: Some(_), .. }, .. }), .. },
+        ] if items.is_empty()
+    );
+    assert!(parsed.tree.expr.is_some());
+
+    let parsed = parse_recovering("let a = 1;\nfn f() {\n    let x = 1;\n", FileId::default());
+    assert_eq!(parsed.errors.len(), 1);
+    assert_matches!(
+        &*parsed.tree.items,
+        [Item {
+            kind: ItemKind::Stmt(_),
+            ..
+        }]
+    );
+
+    assert!(parse_recovering("let a = 1; a", FileId::default())
+        .into_result()
+        .is_ok());
+
+    // `;` and braces in strings, commands and comments don't end items
+    let src = "let s = \"a;b}c\" +;\nlet t = 2 +;\nlet u = $`x;}` // ;}\n+;\nlet v = 1;";
+    let parsed = parse_recovering(src, FileId::default());
+    let lines = parsed
+        .errors
+        .iter()
+        .map(|err| src[..err.span().unwrap().start].lines().count())
+        .collect_vec();
+    assert_eq!(lines, [1, 2, 4]);
+    assert_eq!(parsed.tree.items.len(), 1);
+
+    // Neither do strings in interpolations
+    let src = "let s = \"a ${\"}\"} ;\" +;\nlet t = 2 +;\nlet v = 1;";
+    let parsed = parse_recovering(src, FileId::default());
+    assert_eq!(parsed.errors.len(), 2);
+    assert_eq!(parsed.tree.items.len(), 1);
+}
+
+#[test]
+fn test_code_chars() {
+    let code = |src| CodeChars::new(src).map(|(_, c)| c).collect::<String>();
+    assert_eq!(code(r#"f("{\"}", $`}`) // {"#), "f(, $) ");
+    assert_eq!(code("a // b\nc"), "a c");
+    assert_eq!(
+        CodeChars::new("ab\"c\"d").map(|(i, _)| i).collect_vec(),
+        [0, 1, 5]
+    );
+    // Interpolations are part of their string or command, whatever they contain
+    assert_eq!(code(r#"f("a ${"x"} b", 1) {"#), "f(, 1) {");
+    assert_eq!(code(r#"f("${ #{ a: "}" }.a } $!{"}"}") }"#), "f() }");
+    assert_eq!(code("$`echo ${ $`ls` } $!{ [x] }` ;"), "$ ;");
+    assert_eq!(code(r#""$${" } ("#), " } (");
+
+    let unclosed = |src| {
+        let mut chars = CodeChars::new(src);
+        chars.by_ref().for_each(drop);
+        chars.unclosed()
+    };
+    assert!(unclosed("$`ls\n"));
+    assert!(unclosed("\"a\n"));
+    assert!(unclosed("\"a ${ f(\"x\")"));
+    assert!(!unclosed("\"a ${ f(\"x\") }\" $`b`"));
+}